@@ -7,14 +7,21 @@
 // [`write_str`]: core::fmt::Write::write_str
 // [`write_fmt`]: core::fmt::Write::write_fmt
 
+use crate::interrupt::{IrqMutex, hart_id};
 use crate::sbi::*; // 引入之前写的 console_putchar
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 // 声明一个“空结构体”（Zero-Sized Type），实现 [`core::fmt::Write`] trait 来进行格式化输出
 // ZST 只可能有一个值（即为空），因此它本身就是一个单件
 // 它不占用任何内存空间，仅作为一个载体，用来挂载我们实现的打印方法
 struct Stdout;
 
+// 同一时刻只有一处在打印，时钟中断等处理函数中的打印不会插进普通代码正在打印的一行中间
+static STDOUT: IrqMutex<Stdout> = IrqMutex::new(Stdout);
+// 正在打印的 hart，没有时为 usize::MAX
+static PRINTING_HART: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 impl Write for Stdout {
     // 核心方法：打印一个基础字符串
     // 这是整个格式化系统的“地基”
@@ -41,9 +48,23 @@ impl Write for Stdout {
 // [`core::format_args!`]: https://doc.rust-lang.org/nightly/core/macro.format_args.html
 // 这个函数是 `print!` 和 `println!` 的真正后台，它接收复杂的 Arguments 对象
 pub fn print(args: fmt::Arguments) {
+    let hart = hart_id();
+    // 打印时本 hart 发生了陷入（例如格式化参数时缺页）或者 panic，锁已经被自己持有，直接输出
+    if PRINTING_HART.load(Ordering::Relaxed) == hart {
+        Stdout.write_fmt(args).unwrap();
+        return;
+    }
+    let mut stdout = STDOUT.lock();
+    PRINTING_HART.store(hart, Ordering::Relaxed);
     // 调用 Stdout 的 write_fmt 方法。
     // 注意：write_fmt 是 core::fmt::Write 自动帮我们实现的，
     // 它内部会反复调用我们上面写的 write_str。
+    stdout.write_fmt(args).unwrap();
+    PRINTING_HART.store(usize::MAX, Ordering::Relaxed);
+}
+
+// 不获取控制台的锁直接输出，只用于无法进入临界区时报告致命错误
+pub fn print_unlocked(args: fmt::Arguments) {
     Stdout.write_fmt(args).unwrap();
 }
 
@@ -5,23 +5,54 @@
     .globl _start
 
 # _start 是硬件/固件约定的起始符号，类似于 C/Rust 里的 main
+# 内核被链接在高地址 0xffff_ffff_8020_0000，但 OpenSBI 跳转到这里时还没有开启分页，
+# CPU 实际是在物理地址 0x8020_0000 上执行的。因此在进入 Rust 之前，
+# 需要先装上一个临时的启动页表，再跳到高地址去执行。
+# 注意：这里只使用 t0、t1，不破坏 OpenSBI 传入的 a0、a1。
 _start:
-    # 1. 设置栈指针（Stack Pointer）
-    # la = Load Address (加载地址)
-    # 将 boot_stack_top 这个标签所在的内存地址存入 sp 寄存器
+    # 0. OpenSBI 在 a0 中传入当前的 hart 编号，保存在 tp 中
+    # 内核的其他代码不会修改 tp，出错时（例如栈溢出）可以据此报告是哪个 hart
+    mv tp, a0
+
+    # 1. 计算 boot_page_table 的物理页号
+    # lui 加载的是链接时确定的（高半部分的）虚拟地址，减去偏移量得到物理地址
+    lui t0, %hi(boot_page_table)
+    li t1, 0xffffffff00000000
+    sub t0, t0, t1
+    srli t0, t0, 12
+    # 8 << 60 是 satp 中使用 Sv39 模式的记号
+    li t1, (8 << 60)
+    or t0, t0, t1
+    # 写入 satp 并刷新 TLB，此后所有访存都要经过启动页表翻译
+    csrw satp, t0
+    sfence.vma
+
+    # 2. 设置栈指针（Stack Pointer）
+    # 将 boot_stack_top 这个标签的（高半部分的）虚拟地址存入 sp 寄存器
     # 在 RISC-V 中，sp 寄存器专门用来指向当前的栈顶
     # 当进入 Rust 代码后，一旦调用函数、定义局部变量，Rust 就会尝试向 sp 指向的地址写入数据。
     # 如果这时 sp 是空的（或者是指向了错误的地址），程序会立刻崩溃。
-    la sp, boot_stack_top
+    # 不能用 la：la 是相对当前 pc 计算的，而此时 pc 仍然是物理地址
+    lui sp, %hi(boot_stack_top)
+    addi sp, sp, %lo(boot_stack_top)
 
-    # 2. 跳转到 Rust 编写的主函数
-    # call 指令会跳到 rust_main，并在完成后尝试返回（虽然内核通常不返回）
-    call rust_main
+    # 3. 跳转到 Rust 编写的主函数
+    # 同样使用绝对地址，从物理地址“跳”到高半部分的虚拟地址上继续执行
+    # a0（hart 编号）和 a1（设备树的物理地址）没有被修改，正好作为 rust_main 的两个参数
+    lui t0, %hi(rust_main)
+    addi t0, t0, %lo(rust_main)
+    jr t0
 
 # -------------------------------------------------------------------------
 # 这一部分是数据段，专门用来在内存中预留一块空间给“栈”使用
 # -------------------------------------------------------------------------
     .section .bss.stack
+    # 按 128KB 对齐：下面 64KB 是保护区域，上面 64KB 才是栈（见 memory/mapping/kernel_stack.rs）
+    # 开启分页后保护区域不会被映射，栈溢出会触发缺页异常，而不是悄悄改写 .bss 中的数据
+    .align 17
+    .global boot_stack_guard
+boot_stack_guard:
+    .space 4096 * 16
     .global boot_stack
 
 # 在 RISC-V 架构中，栈是从高地址向低地址增长的。
@@ -36,3 +67,22 @@ boot_stack_top:
     # 这里的标签紧跟在 .space 之后，所以它的地址就是这段空间的末尾
     # 注意：RISC-V 的栈是向低地址增长的，所以 sp 要指向最高处
     # 当 Rust 往栈里压入数据时，地址会变小，正好落在这 64KB 的预留范围内。
+
+# -------------------------------------------------------------------------
+# 启动页表：只有一级，使用 1GB 的大页（gigapage）
+# -------------------------------------------------------------------------
+    .section .data
+    # 页表必须按 4KB 对齐
+    .align 12
+    .global boot_page_table
+boot_page_table:
+    # 第 0、1 项为空
+    .quad 0
+    .quad 0
+    # 第 2 项：0x8000_0000 -> 0x8000_0000，0xcf 表示 VRWXAD 均为 1
+    # 恒等映射，保证写入 satp 之后、跳到高地址之前的几条指令还能继续执行
+    .quad (0x80000 << 10) | 0xcf
+    .zero 507 * 8
+    # 第 510 项：0xffff_ffff_8000_0000 -> 0x8000_0000，0xcf 表示 VRWXAD 均为 1
+    .quad (0x80000 << 10) | 0xcf
+    .quad 0
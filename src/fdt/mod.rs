@@ -0,0 +1,123 @@
+// 设备树模块
+// OpenSBI 启动内核时，在 a0 中传入当前的 hart 编号，在 a1 中传入设备树（DTB）的物理地址。
+// 设备树描述了这台机器的硬件：内存的位置和大小、CPU 的个数、时钟频率、各个外设的寄存器地址和中断号等。
+// 内核从这里读取这些信息，而不是把它们写死在代码里。
+
+mod parser;
+
+#[allow(unused_imports)]
+pub use parser::{Fdt, Node};
+
+use crate::memory::address::{PhysicalAddress, VirtualAddress};
+use core::ops::Range;
+use spin::Once;
+
+// 启动时传入的设备树
+static DEVICE_TREE: Once<Fdt> = Once::new();
+
+// 一个 CPU（hart）的信息
+#[derive(Debug, Copy, Clone)]
+pub struct Cpu {
+    // hart 编号
+    pub hart_id: usize,
+    // 支持的指令集，例如 "rv64imafdc"
+    pub isa: Option<&'static str>,
+    // 是否可用
+    pub enabled: bool,
+}
+
+// 解析启动时传入的设备树
+// 此时还在使用 `entry.asm` 中的启动页表，DTB 通过高半部分的线性映射访问；
+// 内核重新映射后，线性映射仍然覆盖它所在的物理内存。
+pub fn init(dtb: PhysicalAddress) {
+    // 没有传入设备树时 a1 为 0，必须在加上线性映射的偏移之前判断
+    if dtb.0 == 0 {
+        println!("no device tree provided");
+        return;
+    }
+    let pointer = VirtualAddress::from(dtb).as_ptr();
+    match unsafe { Fdt::from_raw(pointer) } {
+        Ok(fdt) => {
+            DEVICE_TREE.call_once(|| fdt);
+            println!("mod fdt initialized");
+        }
+        Err(error) => {
+            println!("invalid device tree at {}: {}", dtb, error);
+        }
+    }
+}
+
+// 启动时传入的设备树，解析失败时为 `None`
+pub fn get() -> Option<&'static Fdt> {
+    DEVICE_TREE.get()
+}
+
+impl Fdt {
+    // DTB 本身占用的物理内存，这部分内存不能分配给其他用途
+    pub fn physical_range(&self) -> Range<PhysicalAddress> {
+        // `init` 通过线性映射访问 DTB，转换不会失败
+        let start = PhysicalAddress::try_from(VirtualAddress(self.address())).unwrap();
+        start..start + self.total_size()
+    }
+
+    // 所有内存区域（`device_type = "memory"` 的节点）
+    pub fn memory_regions(self) -> impl Iterator<Item = Range<PhysicalAddress>> {
+        self.nodes()
+            .filter(|node| {
+                node.depth == 1
+                    && (node.property_str("device_type") == Some("memory")
+                        || node.unit_name() == "memory")
+            })
+            .flat_map(|node| node.reg())
+            .map(|(start, size)| {
+                PhysicalAddress(start as usize)..PhysicalAddress((start + size) as usize)
+            })
+    }
+
+    // `/chosen` 节点中的启动参数
+    pub fn bootargs(&self) -> Option<&'static str> {
+        self.top_level("chosen")?.property_str("bootargs")
+    }
+
+    // `time` 寄存器的频率（Hz），可能写在 `/cpus` 或者某个 CPU 节点中
+    pub fn timebase_frequency(&self) -> Option<u64> {
+        self.top_level("cpus")?
+            .property_u64("timebase-frequency")
+            .or_else(|| {
+                self.cpu_nodes()
+                    .find_map(|node| node.property_u64("timebase-frequency"))
+            })
+    }
+
+    // `/cpus` 下的所有 CPU 节点
+    fn cpu_nodes(self) -> impl Iterator<Item = Node> {
+        self.nodes()
+            .filter(|node| node.depth == 2 && node.parent == "cpus" && node.unit_name() == "cpu")
+    }
+
+    // 所有 CPU
+    pub fn cpus(self) -> impl Iterator<Item = Cpu> {
+        self.cpu_nodes().filter_map(|node| {
+            Some(Cpu {
+                hart_id: node.reg().next()?.0 as usize,
+                isa: node.property_str("riscv,isa"),
+                enabled: node.is_enabled(),
+            })
+        })
+    }
+
+    // 所有设备节点：带有 `compatible` 属性、不在 `/cpus` 之下的节点
+    pub fn devices(self) -> impl Iterator<Item = Node> {
+        self.nodes().filter(|node| {
+            node.depth >= 1 && node.top != "cpus" && node.property("compatible").is_some()
+        })
+    }
+
+    // 与某个驱动兼容且可用的设备
+    #[allow(dead_code)]
+    pub fn find_compatible<'a>(self, names: &'a [&'a str]) -> impl Iterator<Item = Node> + 'a {
+        self.devices().filter(move |node| {
+            node.is_enabled() && names.iter().any(|name| node.is_compatible(name))
+        })
+    }
+}
@@ -0,0 +1,327 @@
+// 扁平设备树（Flattened Device Tree）的解析
+// 设备树二进制（DTB）由头部、内存保留块、结构块和字符串块组成，所有整数都是大端序。
+// 结构块是一串 4 字节对齐的记号：BEGIN_NODE（后跟节点名）、PROP（后跟属性长度、
+// 属性名在字符串块中的偏移和属性值）、END_NODE、NOP 和 END。
+//
+// 这里的解析不分配堆内存，直接在 DTB 上遍历，因此在堆初始化之前也可以使用。
+
+// 头部的魔数
+const MAGIC: u32 = 0xd00d_feed;
+// 结构块中的记号
+const BEGIN_NODE: u32 = 1;
+const END_NODE: u32 = 2;
+const PROP: u32 = 3;
+const NOP: u32 = 4;
+// 支持的最大嵌套深度
+const MAX_DEPTH: usize = 16;
+// 没有 `#address-cells` / `#size-cells` 属性时的默认值
+const DEFAULT_ADDRESS_CELLS: usize = 2;
+const DEFAULT_SIZE_CELLS: usize = 1;
+
+// 读取大端序的 u32
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// 读取 `offset` 处以 0 结尾的字符串
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let length = bytes.iter().position(|&byte| byte == 0)?;
+    core::str::from_utf8(&bytes[..length]).ok()
+}
+
+// 把若干个 u32 拼成一个数，地址和大小都可能由一个或两个 u32 组成
+fn read_cells(data: &[u8], cells: usize) -> u64 {
+    (0..cells).fold(0, |value, i| {
+        (value << 32) | read_u32(data, i * 4).unwrap_or(0) as u64
+    })
+}
+
+// 一棵设备树
+#[derive(Debug, Copy, Clone)]
+pub struct Fdt {
+    // 整个 DTB
+    data: &'static [u8],
+    // 结构块
+    structure: &'static [u8],
+    // 字符串块
+    strings: &'static [u8],
+}
+
+impl Fdt {
+    // 从 DTB 的起始地址解析设备树，检查头部是否合法
+    //
+    // # Safety
+    // `pointer` 必须指向一个完整的、在内核运行期间一直有效的 DTB
+    pub unsafe fn from_raw(pointer: *const u8) -> Result<Self, &'static str> {
+        if !(pointer as usize).is_multiple_of(4) {
+            return Err("device tree pointer is misaligned");
+        }
+        // 先读出头部，得到整个 DTB 的大小
+        let header = unsafe { core::slice::from_raw_parts(pointer, 40) };
+        if read_u32(header, 0) != Some(MAGIC) {
+            return Err("bad device tree magic");
+        }
+        let total_size = read_u32(header, 4).unwrap() as usize;
+        let data = unsafe { core::slice::from_raw_parts(pointer, total_size) };
+        let field = |offset| read_u32(data, offset).unwrap() as usize;
+        // 第 20 字节开始依次为 version 和 last_comp_version，要求兼容第 17 版
+        if field(20) < 17 || field(24) > 17 {
+            return Err("unsupported device tree version");
+        }
+        let structure = data
+            .get(field(8)..field(8) + field(36))
+            .ok_or("device tree structure block out of range")?;
+        let strings = data
+            .get(field(12)..field(12) + field(32))
+            .ok_or("device tree strings block out of range")?;
+        Ok(Self {
+            data,
+            structure,
+            strings,
+        })
+    }
+
+    // DTB 的起始地址
+    pub fn address(&self) -> usize {
+        self.data.as_ptr() as usize
+    }
+
+    // DTB 的总大小（字节）
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    // 按深度优先的顺序遍历所有节点
+    pub fn nodes(self) -> NodeIter {
+        let mut cells = [(0, 0); MAX_DEPTH + 1];
+        cells[0] = (DEFAULT_ADDRESS_CELLS, DEFAULT_SIZE_CELLS);
+        NodeIter {
+            fdt: self,
+            offset: 0,
+            depth: 0,
+            names: [""; MAX_DEPTH],
+            cells,
+        }
+    }
+
+    // 根节点下名为 `name` 的节点，`name` 可以省略 `@` 后的单元地址
+    pub fn top_level(&self, name: &str) -> Option<Node> {
+        self.nodes()
+            .find(|node| node.depth == 1 && (node.name == name || node.unit_name() == name))
+    }
+
+    // 通过 phandle 查找节点
+    #[allow(dead_code)]
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node> {
+        self.nodes().find(|node| node.phandle() == Some(phandle))
+    }
+}
+
+// 设备树中的一个节点
+#[derive(Debug, Copy, Clone)]
+pub struct Node {
+    fdt: Fdt,
+    // 节点名，例如 `uart@10000000`
+    pub name: &'static str,
+    // 深度，根节点为 0
+    pub depth: usize,
+    // 父节点名，根节点为空
+    pub parent: &'static str,
+    // 所在的顶层节点名（根节点的直接子节点），例如 `cpus`、`soc`
+    pub top: &'static str,
+    // 父节点规定的地址和大小所占的 u32 个数，用于解析 `reg`
+    address_cells: usize,
+    size_cells: usize,
+    // 第一个属性在结构块中的偏移
+    properties: usize,
+}
+
+impl Node {
+    // 去掉 `@` 后单元地址的节点名
+    pub fn unit_name(&self) -> &'static str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    // 遍历节点的所有属性，每一项为 (属性名, 属性值)
+    pub fn properties(self) -> PropertyIter {
+        PropertyIter {
+            fdt: self.fdt,
+            offset: self.properties,
+        }
+    }
+
+    // 名为 `name` 的属性的值
+    pub fn property(&self, name: &str) -> Option<&'static [u8]> {
+        self.properties()
+            .find(|&(property, _)| property == name)
+            .map(|(_, value)| value)
+    }
+
+    // 字符串属性的值
+    pub fn property_str(&self, name: &str) -> Option<&'static str> {
+        read_str(self.property(name)?, 0)
+    }
+
+    // 整数属性的值，可以由一个或两个 u32 组成
+    pub fn property_u64(&self, name: &str) -> Option<u64> {
+        let value = self.property(name)?;
+        match value.len() {
+            4 | 8 => Some(read_cells(value, value.len() / 4)),
+            _ => None,
+        }
+    }
+
+    // `compatible` 属性列出的所有兼容的驱动名
+    pub fn compatible(self) -> impl Iterator<Item = &'static str> {
+        self.property("compatible")
+            .unwrap_or(&[])
+            .split(|&byte| byte == 0)
+            .filter_map(|name| core::str::from_utf8(name).ok())
+            .filter(|name| !name.is_empty())
+    }
+
+    // 是否与某个驱动兼容
+    #[allow(dead_code)]
+    pub fn is_compatible(&self, name: &str) -> bool {
+        self.compatible().any(|compatible| compatible == name)
+    }
+
+    // `reg` 属性中的每一段 (地址, 大小)
+    pub fn reg(self) -> impl Iterator<Item = (u64, u64)> {
+        let (address_cells, size_cells) = (self.address_cells, self.size_cells);
+        let stride = (address_cells + size_cells) * 4;
+        self.property("reg")
+            .filter(|_| stride != 0)
+            .unwrap_or(&[])
+            .chunks_exact(stride.max(4))
+            .map(move |chunk| {
+                (
+                    read_cells(chunk, address_cells),
+                    read_cells(&chunk[address_cells * 4..], size_cells),
+                )
+            })
+    }
+
+    // `interrupts` 属性中的每一个 u32，其含义由中断控制器决定（对 PLIC 来说就是中断号）
+    pub fn interrupts(self) -> impl Iterator<Item = u32> {
+        self.property("interrupts")
+            .unwrap_or(&[])
+            .chunks_exact(4)
+            .map(|chunk| read_u32(chunk, 0).unwrap())
+    }
+
+    // 负责这个设备的中断控制器的 phandle
+    #[allow(dead_code)]
+    pub fn interrupt_parent(&self) -> Option<u32> {
+        self.property_u64("interrupt-parent")
+            .map(|value| value as u32)
+    }
+
+    // 节点自身的 phandle，其他节点通过它引用这个节点
+    #[allow(dead_code)]
+    pub fn phandle(&self) -> Option<u32> {
+        self.property_u64("phandle").map(|value| value as u32)
+    }
+
+    // `status` 属性不存在或为 "okay" 时，设备可用
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.property_str("status"), None | Some("okay" | "ok"))
+    }
+}
+
+// 节点的遍历器
+pub struct NodeIter {
+    fdt: Fdt,
+    offset: usize,
+    depth: usize,
+    // 从根节点到当前位置的各层节点名
+    names: [&'static str; MAX_DEPTH],
+    // cells[i] 为深度 i 的节点解析 `reg` 时使用的 (#address-cells, #size-cells)
+    cells: [(usize, usize); MAX_DEPTH + 1],
+}
+
+impl Iterator for NodeIter {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let structure = self.fdt.structure;
+        loop {
+            match read_u32(structure, self.offset)? {
+                BEGIN_NODE => {
+                    if self.depth == MAX_DEPTH {
+                        return None;
+                    }
+                    let name = read_str(structure, self.offset + 4)?;
+                    let properties = (self.offset + 4 + name.len() + 1).next_multiple_of(4);
+                    let (address_cells, size_cells) = self.cells[self.depth];
+                    let node = Node {
+                        fdt: self.fdt,
+                        name,
+                        depth: self.depth,
+                        parent: if self.depth == 0 {
+                            ""
+                        } else {
+                            self.names[self.depth - 1]
+                        },
+                        top: if self.depth <= 1 { name } else { self.names[1] },
+                        address_cells,
+                        size_cells,
+                        properties,
+                    };
+                    // 子节点使用这个节点规定的 cells
+                    let cells = |property| node.property_u64(property).map(|value| value as usize);
+                    self.cells[self.depth + 1] = (
+                        cells("#address-cells").unwrap_or(DEFAULT_ADDRESS_CELLS),
+                        cells("#size-cells").unwrap_or(DEFAULT_SIZE_CELLS),
+                    );
+                    self.names[self.depth] = name;
+                    self.depth += 1;
+                    self.offset = properties;
+                    return Some(node);
+                }
+                END_NODE => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset += 4;
+                }
+                PROP => {
+                    let length = read_u32(structure, self.offset + 4)? as usize;
+                    self.offset = (self.offset + 12 + length).next_multiple_of(4);
+                }
+                NOP => self.offset += 4,
+                // END 或者无法识别的记号，结束遍历
+                _ => return None,
+            }
+        }
+    }
+}
+
+// 一个节点的属性的遍历器
+pub struct PropertyIter {
+    fdt: Fdt,
+    offset: usize,
+}
+
+impl Iterator for PropertyIter {
+    type Item = (&'static str, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structure = self.fdt.structure;
+        loop {
+            match read_u32(structure, self.offset)? {
+                PROP => {
+                    let length = read_u32(structure, self.offset + 4)? as usize;
+                    let name_offset = read_u32(structure, self.offset + 8)? as usize;
+                    let name = read_str(self.fdt.strings, name_offset)?;
+                    let value = structure.get(self.offset + 12..self.offset + 12 + length)?;
+                    self.offset = (self.offset + 12 + length).next_multiple_of(4);
+                    return Some((name, value));
+                }
+                NOP => self.offset += 4,
+                // 遇到子节点或节点结束，属性部分已经结束
+                _ => return None,
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 // 引入 riscv 库中封装好的寄存器类型
-use riscv::register::sstatus::Sstatus;
+use riscv::register::sstatus::SPP;
+use core::mem::size_of;
 
 // Context：程序的瞬间快照
 // 当硬件中断（比如时钟中断或键盘输入）发生时，CPU 会强行停下当前正在运行的代码，转去执行“中断处理程序”。
@@ -8,6 +9,9 @@ use riscv::register::sstatus::Sstatus;
 // #[repr(C)] 是非常关键的属性，保证内存顺序：x[0], x[1]...x[31], sstatus, sepc。
 // 它告诉 Rust 编译器：这个结构体在内存中的布局必须和 C 语言标准一致。
 // 这样在后续写汇编代码来手动保存/恢复这些寄存器时，能准确知道每个成员的偏移位置。
+//
+// 从用户态陷入时，`__interrupt` 通过 sscratch 切换到这个任务的内核栈，Context 就放在内核栈的最顶端；
+// 最后两项只在返回用户态时由 `__restore` 填写，供下一次从用户态陷入时使用。
 #[repr(C)]
 #[derive(Debug)]
 pub struct Context {
@@ -19,10 +23,68 @@ pub struct Context {
     // sstatus (Supervisor Status) 寄存器
     // 记录了 CPU 当前的状态，比如是否开启中断、之前的特权级是什么等。
     // 中断处理可能会改变状态，所以必须保存原样。
-    pub sstatus: Sstatus,
+    // 保存的是寄存器的原始值，各个字段通过 `spp()`、`spie()` 等方法读取
+    pub sstatus: usize,
 
     // sepc (Supervisor Exception Program Counter) 寄存器
     // 极其重要！它记录了中断发生那一刻，程序运行到了哪一行指令（地址）。
     // 恢复上下文时，会把这个值放回 PC，程序就能从断点处继续。
-    pub sepc: usize
+    pub sepc: usize,
+
+    // 内核栈的栈顶
+    // 返回用户态之前由 `__restore` 写入 sscratch，下一次从用户态陷入时就切换到这里
+    pub kernel_sp: usize,
+
+    // 内核使用的 tp（hart 编号）
+    // 用户程序可以随意修改 tp，从用户态陷入时从这里恢复
+    pub kernel_tp: usize,
+}
+
+// Context 的大小（字节），必须与 `interrupt.asm` 中的 CONTEXT_SIZE 一致
+pub const CONTEXT_SIZE: usize = size_of::<Context>();
+const _: () = assert!(CONTEXT_SIZE == 36 * 8);
+
+// sstatus 中的字段
+const SSTATUS_SIE: usize = 1 << 1;
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+
+impl Context {
+    // 一个新的用户态上下文：sret 之后从 `entry` 开始执行，栈指针为 `user_sp`
+    // SPP 为 User，SPIE 为 1，进入用户态后允许中断；其余寄存器为 0
+    pub fn new_user(entry: usize, user_sp: usize) -> Self {
+        let mut x = [0; 32];
+        x[2] = user_sp;
+        Self {
+            x,
+            sstatus: SSTATUS_SPIE,
+            sepc: entry,
+            kernel_sp: 0,
+            kernel_tp: 0,
+        }
+    }
+
+    // 陷入之前是否处于用户态
+    pub fn is_user(&self) -> bool {
+        self.spp() == SPP::User
+    }
+
+    // 陷入之前的特权级（SPP）
+    pub fn spp(&self) -> SPP {
+        if self.sstatus & SSTATUS_SPP == 0 {
+            SPP::User
+        } else {
+            SPP::Supervisor
+        }
+    }
+
+    // 陷入之前是否允许中断（SPIE），sret 时恢复到 SIE
+    pub fn spie(&self) -> bool {
+        self.sstatus & SSTATUS_SPIE != 0
+    }
+
+    // 陷入时 S 态中断是否打开（SIE），陷入后硬件总是将其清零
+    pub fn sie(&self) -> bool {
+        self.sstatus & SSTATUS_SIE != 0
+    }
 }
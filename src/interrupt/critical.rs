@@ -0,0 +1,139 @@
+// 关闭中断的临界区
+// 中断处理函数和普通的内核代码可能访问同一份数据（例如时钟计数、控制台、注册表）。
+// 普通代码持有自旋锁时如果被中断，而中断处理函数又去获取同一把锁，这个 hart 就会永远死锁，
+// 因此访问这样的数据时要先关闭本 hart 的中断。
+//
+// [`push_off`] / [`pop_off`] 可以嵌套：每个 hart 记录嵌套的深度，以及最外层 `push_off` 之前中断是否打开，
+// 最外层的 `pop_off` 才恢复原来的状态。这样持有多把锁、或者在临界区中调用同样会关闭中断的函数都不会提前打开中断。
+// 一般不直接调用它们，而是使用 RAII 的 [`InterruptGuard`]，或者获取锁时自动关闭中断的 [`IrqMutex`]。
+//
+// 深度只由本 hart 在关闭中断时修改，不会和其他 hart 或中断处理函数竞争。
+
+use super::handler::{MAX_HARTS, current_hart};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::sstatus;
+use spin::{Mutex, MutexGuard};
+
+// 每个 hart 上临界区的嵌套深度
+static DEPTH: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+// 每个 hart 在最外层 `push_off` 之前中断是否打开
+static ENABLED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+
+// 关闭本 hart 的中断，进入一层临界区
+pub fn push_off() {
+    let enabled = sstatus::read().sie();
+    unsafe { sstatus::clear_sie() };
+    let hart = current_hart();
+    if DEPTH[hart].load(Ordering::Relaxed) == 0 {
+        ENABLED[hart].store(enabled, Ordering::Relaxed);
+    }
+    DEPTH[hart].fetch_add(1, Ordering::Relaxed);
+}
+
+// 离开一层临界区，离开最外层时恢复进入之前的中断状态
+pub fn pop_off() {
+    assert!(!sstatus::read().sie(), "pop_off: interrupts are enabled");
+    let hart = current_hart();
+    let depth = DEPTH[hart].load(Ordering::Relaxed);
+    assert!(depth > 0, "pop_off: not in a critical section");
+    DEPTH[hart].store(depth - 1, Ordering::Relaxed);
+    if depth == 1 && ENABLED[hart].load(Ordering::Relaxed) {
+        unsafe { sstatus::set_sie() };
+    }
+}
+
+// 本 hart 上临界区的嵌套深度
+pub fn depth() -> usize {
+    DEPTH[current_hart()].load(Ordering::Relaxed)
+}
+
+// 在临界区中执行 `f`
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = InterruptGuard::new();
+    f()
+}
+
+// 存在期间关闭本 hart 的中断，drop 时离开临界区
+// 只能在创建它的 hart 上 drop，因此不实现 Send
+pub struct InterruptGuard {
+    _not_send: core::marker::PhantomData<*mut ()>,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        push_off();
+        Self {
+            _not_send: core::marker::PhantomData,
+        }
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        pop_off();
+    }
+}
+
+// 获取锁时关闭本 hart 中断的自旋锁，可以同时在中断处理函数和普通代码中使用
+pub struct IrqMutex<T> {
+    inner: Mutex<T>,
+}
+
+// 持有 [`IrqMutex`] 的锁，drop 时先释放锁再恢复中断
+// 字段按声明的顺序 drop
+pub struct IrqMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    _interrupts: InterruptGuard,
+}
+
+#[allow(dead_code)]
+impl<T> IrqMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+        }
+    }
+
+    // 关闭中断后获取锁
+    pub fn lock(&self) -> IrqMutexGuard<'_, T> {
+        let interrupts = InterruptGuard::new();
+        IrqMutexGuard {
+            guard: self.inner.lock(),
+            _interrupts: interrupts,
+        }
+    }
+
+    // 尝试获取锁，锁已被持有时恢复中断并返回 None
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<'_, T>> {
+        let interrupts = InterruptGuard::new();
+        Some(IrqMutexGuard {
+            guard: self.inner.try_lock()?,
+            _interrupts: interrupts,
+        })
+    }
+
+    // 锁是否被持有
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl<T> Deref for IrqMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
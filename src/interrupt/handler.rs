@@ -1,6 +1,9 @@
-use core::arch::global_asm;
-use super::context::Context;
-use riscv::register::stvec;
+use core::arch::{asm, global_asm};
+use super::context::{Context, CONTEXT_SIZE};
+use super::instruction;
+use super::registry::{self, PRIORITY_DEFAULT, TrapResult, TrapSource};
+use crate::memory::{address::VirtualAddress, mapping::{self, AccessType, Privilege}};
+use riscv::register::{sscratch, sstatus, stvec};
 use riscv::register::scause::{Scause, Trap, Exception, Interrupt};
 
 // 1. 嵌入汇编代码
@@ -29,6 +32,58 @@ pub fn init() {
         //         这是“直接模式”。意味着无论是时钟中断、非法指令还是系统调用，
         //         CPU 统统跳到同一个入口（__interrupt）去处理。
         stvec::write(__interrupt as *const () as usize, stvec::TrapMode::Direct);
+        // 4. 在内核态运行时 sscratch 为 0（见 `interrupt.asm`）
+        sscratch::write(0);
+    }
+    register_default_handlers();
+}
+
+// 注册内核自带的处理函数
+// 同一种陷入上有多个处理函数时，先注册只处理特殊情况的那个（优先级更小），其余的交给后面的通用处理
+fn register_default_handlers() {
+    // riscv 库的 Exception 中没有 LoadMisaligned，直接使用异常号 4
+    let handlers: [(TrapSource, u8, &'static str, registry::TrapHandler); 12] = [
+        (Exception::Breakpoint.into(), PRIORITY_DEFAULT, "breakpoint", breakpoint),
+        (Exception::UserEnvCall.into(), PRIORITY_DEFAULT, "syscall", syscall),
+        (Exception::LoadFault.into(), PRIORITY_DEFAULT, "load fault", load_fault),
+        (TrapSource::Exception(4), PRIORITY_DEFAULT, "misaligned", misaligned),
+        (Exception::StoreMisaligned.into(), PRIORITY_DEFAULT, "misaligned", misaligned),
+        (Exception::LoadPageFault.into(), PRIORITY_DEFAULT - 2, "null pointer", null_pointer),
+        (Exception::LoadPageFault.into(), PRIORITY_DEFAULT - 1, "stack guard", stack_guard),
+        (Exception::StorePageFault.into(), PRIORITY_DEFAULT - 1, "stack guard", stack_guard),
+        (Exception::LoadPageFault.into(), PRIORITY_DEFAULT, "page fault", page_fault),
+        (Exception::StorePageFault.into(), PRIORITY_DEFAULT, "page fault", page_fault),
+        (Exception::InstructionPageFault.into(), PRIORITY_DEFAULT, "page fault", page_fault),
+        (Interrupt::SupervisorTimer.into(), PRIORITY_DEFAULT, "timer", supervisor_timer),
+    ];
+    for (source, priority, name, handler) in handlers {
+        registry::register(source, priority, name, handler).unwrap();
+    }
+}
+
+// 进入一个新的用户态上下文，不会返回
+// Context 被放在 `kernel_stack_top` 下方，也就是内核栈的最顶端，然后经由 `__restore` 执行 sret。
+// 之后用户程序每次陷入，都会切换到这个内核栈上，Context 也保存在同样的位置。
+//
+// # Safety
+// `context` 必须是用户态的上下文（SPP 为 User），其中的入口地址和栈指针在当前地址空间中可以访问；
+// `kernel_stack_top` 必须是一个空闲的内核栈的栈顶，当前的栈不会再被使用。
+#[allow(dead_code)]
+pub unsafe fn enter_user(context: Context, kernel_stack_top: usize) -> ! {
+    unsafe extern "C" {
+        fn __restore();
+    }
+    assert!(context.is_user(), "context does not return to user mode");
+    let frame = (kernel_stack_top - CONTEXT_SIZE) as *mut Context;
+    unsafe {
+        frame.write(context);
+        asm!(
+            "mv sp, {frame}",
+            "j {restore}",
+            frame = in(reg) frame,
+            restore = sym __restore,
+            options(noreturn)
+        )
     }
 }
 
@@ -43,44 +98,155 @@ pub fn handle_interrupt(context: &mut Context, scause: Scause, stval: usize) {
     let cause = scause.cause();
     // 可以通过 Debug 来查看发生了什么中断
     println!("{:x?}", cause);
-    match cause {
-        // 断点中断（ebreak）
-        Trap::Exception(Exception::Breakpoint) => breakpoint(context, 2),
-        // 捕获非法内存访问 (LoadFault)
-        // 当程序尝试读取非法地址（如 0x0）时，硬件会触发这个异常
-        Trap::Exception(Exception::LoadFault) => {
-            // stval 记录了触发异常的那个非法地址
-            if stval == 0x0 {
-                println!("SUCCESS!");
-            }
-            breakpoint(context, 4);
-        },
-        // 时钟中断
-        // 通过 super 调用同级目录下的 timer 模块
-        Trap::Interrupt(Interrupt::SupervisorTimer) => supervisor_timer(),
-        // 其他情况，调用故障处理
-        _ => fault(context, scause, stval),
+    // 依次交给注册的处理函数（见 `registry.rs`），都没有处理时调用故障处理
+    if registry::dispatch(context, scause, stval) == TrapResult::Pass {
+        fault(context, scause, stval);
     }
 }
 
-
 // 处理 ebreak 断点
-// sepc 记录的是触发中断的指令地址。对于 ebreak，我们手动 +2 字节跳过它。
-// 在 RISC-V 中
-// ebreak 指令占 2 个字节。当 ebreak 触发中断时，sepc 指向的是 ebreak 本身。
-// 如果我们不手动 +2，中断返回后 CPU 又会执行 ebreak，导致陷入死循环。
-// main.rs 中的 `ld t0, (x0)` 是一条 4 字节指令，
-// 我们必须手动将 sepc 增加 4，才能跳过它执行下一条语句。
-fn breakpoint(context: &mut Context, offset: usize) {
+// sepc 记录的是触发中断的指令地址。当 ebreak 触发中断时，sepc 指向的是 ebreak 本身，
+// 如果不跳过它，中断返回后 CPU 又会执行 ebreak，导致陷入死循环。
+// 在 RISC-V 中 ebreak 有 4 字节的 `ebreak` 和 2 字节的 `c.ebreak` 两种形式，跳过的长度由解码得到。
+fn breakpoint(context: &mut Context, _: Scause, _: usize) -> TrapResult {
+    skip_instruction(context);
+    TrapResult::Handled
+}
+
+// 捕获非法内存访问 (LoadFault)
+// 当程序尝试读取非法地址（如 0x0）时，硬件会触发这个异常，stval 记录了触发异常的那个非法地址。
+// 跳过出错的指令执行下一条语句，例如 main.rs 中 4 字节的 `ld t0, (x0)`；压缩的 load 只有 2 字节。
+fn load_fault(context: &mut Context, _: Scause, stval: usize) -> TrapResult {
+    if stval == 0x0 {
+        println!("SUCCESS!");
+    }
+    skip_instruction(context);
+    TrapResult::Handled
+}
+
+// 开启分页后，0x0 所在的低半部分没有映射，读取它触发的是 LoadPageFault
+// 只跳过 main.rs 中演示用的 `ld t0, (x0)`：内核态中以 x0 为基址、偏移为 0 的 load。
+// 其他的空指针访问（包括来自用户态的）仍然是真正的错误，交给后面的处理函数
+fn null_pointer(context: &mut Context, scause: Scause, stval: usize) -> TrapResult {
+    if stval != 0x0 || context.is_user() {
+        return TrapResult::Pass;
+    }
+    match instruction::fetch(context).operation {
+        instruction::Operation::Load { base: 0, offset: 0, .. } => load_fault(context, scause, stval),
+        _ => TrapResult::Pass,
+    }
+}
+
+// 跳过触发异常的指令
+fn skip_instruction(context: &mut Context) {
     println!("Breakpoint at 0x{:x}", context.sepc);
-    context.sepc += offset;
+    instruction::skip(context);
+}
+
+// 非对齐的 load / store（异常号 4 和 6）
+// 硬件不支持非对齐访问时，用逐字节的访问模拟出错的指令，再返回到下一条指令
+fn misaligned(context: &mut Context, _: Scause, stval: usize) -> TrapResult {
+    match instruction::emulate_misaligned(context, stval) {
+        Ok(()) => TrapResult::Handled,
+        Err(error) => {
+            println!("Misaligned access at 0x{:x}: {}", stval, error);
+            TrapResult::Pass
+        }
+    }
+}
+
+// 处理系统调用
+// a7 为系统调用号，a0 ~ a5 为参数，返回值写回 a0；
+// ecall 指令占 4 个字节，返回时跳过它
+fn syscall(context: &mut Context, _: Scause, _: usize) -> TrapResult {
+    let args = [
+        context.x[10],
+        context.x[11],
+        context.x[12],
+        context.x[13],
+        context.x[14],
+        context.x[15],
+    ];
+    context.x[10] = crate::syscall::syscall(context.x[17], args) as usize;
+    context.sepc += 4;
+    TrapResult::Handled
 }
 
 // 处理时钟中断
 // 目前只会在 [`timer`] 模块中进行计数
-fn supervisor_timer() {
+fn supervisor_timer(_: &mut Context, _: Scause, _: usize) -> TrapResult {
     // 调用 timer 模块中的 tick 函数
     super::timer::tick();
+    TrapResult::Handled
+}
+
+// 访问了内核栈下方的保护区域
+fn stack_guard(context: &mut Context, _: Scause, stval: usize) -> TrapResult {
+    if !mapping::is_guard_page(VirtualAddress(stval)) {
+        return TrapResult::Pass;
+    }
+    handle_stack_overflow(hart_id(), context.x[2], context.sepc, stval)
+}
+
+// 处理缺页异常
+// 能在当前地址空间中按需分配的，分配好物理页后直接返回，重新执行出错的指令即可；
+// 否则就是真正的非法访问。目前还没有用户进程可以结束，交给后面的处理函数，最终由 [`fault`] 处理。
+fn page_fault(context: &mut Context, scause: Scause, stval: usize) -> TrapResult {
+    let access = match scause.cause() {
+        Trap::Exception(Exception::StorePageFault) => AccessType::Store,
+        Trap::Exception(Exception::InstructionPageFault) => AccessType::Execute,
+        _ => AccessType::Load,
+    };
+    // 陷入不会改变 SUM，此时读到的就是出错时的值
+    let privilege = if context.is_user() {
+        Privilege::User
+    } else {
+        Privilege::Supervisor { sum: sstatus::read().sum() }
+    };
+    match mapping::handle_page_fault(VirtualAddress(stval), access, privilege) {
+        Ok(_) => TrapResult::Handled,
+        Err(error) => {
+            println!("Invalid {:?} access at 0x{:x}: {}", access, stval, error);
+            TrapResult::Pass
+        }
+    }
+}
+
+// 内核栈溢出
+// 通常由 `interrupt.asm` 在发现栈指针落入保护区域时直接跳转过来（此时已经换到了专用的栈上），
+// 也可能是某个很大的栈帧越过了栈底，访问保护区域时触发了缺页异常
+#[unsafe(no_mangle)]
+pub extern "C" fn handle_stack_overflow(hart: usize, sp: usize, sepc: usize, stval: usize) -> ! {
+    panic!(
+        "kernel stack overflow on hart {} at sepc 0x{:x} (sp: 0x{:x}, stval: 0x{:x})",
+        hart, sepc, sp, stval
+    );
+}
+
+// 内核支持的最多 hart 个数，按 hart 编号记录的状态都使用这个大小的数组
+pub const MAX_HARTS: usize = 8;
+
+// 当前的 hart 编号，由 `entry.asm` 保存在 tp（x4）中
+// 从用户态陷入时，`interrupt.asm` 已经恢复了内核的 tp，Context 中保存的则是用户程序的 tp
+pub fn hart_id() -> usize {
+    let hart: usize;
+    unsafe { asm!("mv {}, tp", out(reg) hart) };
+    hart
+}
+
+// 当前的 hart 编号，用作按 hart 记录的状态（数组下标、位图中的位）
+// 编号超出 MAX_HARTS 的 hart 没有对应的状态，而打印本身也要进入临界区（见 `critical.rs`），
+// 因此绕过控制台的锁报告错误后直接关机，不能 panic
+pub fn current_hart() -> usize {
+    let hart = hart_id();
+    if hart >= MAX_HARTS {
+        crate::console::print_unlocked(format_args!(
+            "hart {} is not supported: at most {} harts\n",
+            hart, MAX_HARTS
+        ));
+        crate::sbi::shutdown();
+    }
+    hart
 }
 
 // 出现未能解决的异常
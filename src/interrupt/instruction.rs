@@ -0,0 +1,299 @@
+// RISC-V 指令的解码
+// 处理异常时常常要知道 sepc 处是什么指令：跳过一条指令时需要它的真实长度
+// （`ebreak` 有 4 字节的 `ebreak` 和 2 字节的 `c.ebreak` 两种形式，访存指令也都有压缩形式），
+// 模拟非对齐访存时还需要知道访问的宽度、符号扩展和目标寄存器。
+//
+// 指令长度由最低的几位决定：
+// - 最低两位不是 11：16 位的压缩指令
+// - 最低五位不是 11111：32 位指令
+// - 最低六位是 011111：48 位指令；最低七位是 0111111：64 位指令
+//
+// 目前只解码整数的访存指令（RV64I 和 RVC 中的 load / store），其余指令都是 [`Operation::Other`]。
+
+use super::context::Context;
+use crate::memory::address::VirtualAddress;
+use crate::memory::mapping::{self, AccessType};
+use riscv::register::sstatus;
+
+// 解码后的指令
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Instruction {
+    // 指令的字节数
+    pub length: usize,
+    pub operation: Operation,
+}
+
+// 指令的操作
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Operation {
+    // 从 x[base] + offset 读取 `width` 字节到 x[rd]，`signed` 时符号扩展
+    Load {
+        rd: usize,
+        base: usize,
+        offset: isize,
+        width: usize,
+        signed: bool,
+    },
+    // 把 x[rs2] 的低 `width` 字节写到 x[base] + offset
+    Store {
+        rs2: usize,
+        base: usize,
+        offset: isize,
+        width: usize,
+    },
+    // 其他指令
+    Other,
+}
+
+// 由指令的最低 16 位得到指令的字节数
+pub fn length(low: u16) -> usize {
+    if low & 0b11 != 0b11 {
+        2
+    } else if low & 0b1_1100 != 0b1_1100 {
+        4
+    } else if low & 0b11_1111 == 0b01_1111 {
+        6
+    } else if low & 0b111_1111 == 0b011_1111 {
+        8
+    } else {
+        // 更长的指令还没有定义，按 32 位处理
+        4
+    }
+}
+
+// 指令中 [high:low] 位的值
+fn bits(instruction: u32, high: u32, low: u32) -> u32 {
+    (instruction >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+// 把最低 `width` 位符号扩展
+fn sign_extend(value: u32, width: u32) -> isize {
+    let shift = 32 - width;
+    ((value << shift) as i32 >> shift) as isize
+}
+
+// 解码一条指令，`instruction` 为从指令地址开始的 32 位（压缩指令只用到低 16 位）
+pub fn decode(instruction: u32) -> Instruction {
+    let length = length(instruction as u16);
+    let operation = match length {
+        2 => decode_compressed(instruction as u16),
+        4 => decode_standard(instruction),
+        _ => Operation::Other,
+    };
+    Instruction { length, operation }
+}
+
+// 32 位的 load / store
+fn decode_standard(instruction: u32) -> Operation {
+    let funct3 = bits(instruction, 14, 12);
+    let base = bits(instruction, 19, 15) as usize;
+    match bits(instruction, 6, 0) {
+        // LB / LH / LW / LD / LBU / LHU / LWU
+        0b000_0011 if funct3 != 0b111 => Operation::Load {
+            rd: bits(instruction, 11, 7) as usize,
+            base,
+            offset: sign_extend(bits(instruction, 31, 20), 12),
+            width: 1 << (funct3 & 0b11),
+            signed: funct3 & 0b100 == 0,
+        },
+        // SB / SH / SW / SD
+        0b010_0011 if funct3 <= 0b011 => Operation::Store {
+            rs2: bits(instruction, 24, 20) as usize,
+            base,
+            offset: sign_extend(
+                bits(instruction, 31, 25) << 5 | bits(instruction, 11, 7),
+                12,
+            ),
+            width: 1 << funct3,
+        },
+        _ => Operation::Other,
+    }
+}
+
+// 压缩指令中的 load / store
+fn decode_compressed(instruction: u16) -> Operation {
+    let instruction = instruction as u32;
+    let funct3 = bits(instruction, 15, 13);
+    // 压缩指令中三位的寄存器编号表示 x8 ~ x15
+    let rd_prime = 8 + bits(instruction, 4, 2) as usize;
+    let rs1_prime = 8 + bits(instruction, 9, 7) as usize;
+    // C.LW / C.SW 的偏移：uimm[5:3] = [12:10]，uimm[2] = [6]，uimm[6] = [5]
+    let word_offset = (bits(instruction, 12, 10) << 3
+        | bits(instruction, 6, 6) << 2
+        | bits(instruction, 5, 5) << 6) as isize;
+    // C.LD / C.SD 的偏移：uimm[5:3] = [12:10]，uimm[7:6] = [6:5]
+    let double_offset = (bits(instruction, 12, 10) << 3 | bits(instruction, 6, 5) << 6) as isize;
+    let rd = bits(instruction, 11, 7) as usize;
+    let rs2 = bits(instruction, 6, 2) as usize;
+    match (bits(instruction, 1, 0), funct3) {
+        // C.LW
+        (0b00, 0b010) => Operation::Load {
+            rd: rd_prime,
+            base: rs1_prime,
+            offset: word_offset,
+            width: 4,
+            signed: true,
+        },
+        // C.LD
+        (0b00, 0b011) => Operation::Load {
+            rd: rd_prime,
+            base: rs1_prime,
+            offset: double_offset,
+            width: 8,
+            signed: true,
+        },
+        // C.SW
+        (0b00, 0b110) => Operation::Store {
+            rs2: rd_prime,
+            base: rs1_prime,
+            offset: word_offset,
+            width: 4,
+        },
+        // C.SD
+        (0b00, 0b111) => Operation::Store {
+            rs2: rd_prime,
+            base: rs1_prime,
+            offset: double_offset,
+            width: 8,
+        },
+        // C.LWSP：uimm[5] = [12]，uimm[4:2] = [6:4]，uimm[7:6] = [3:2]
+        (0b10, 0b010) if rd != 0 => Operation::Load {
+            rd,
+            base: 2,
+            offset: (bits(instruction, 12, 12) << 5
+                | bits(instruction, 6, 4) << 2
+                | bits(instruction, 3, 2) << 6) as isize,
+            width: 4,
+            signed: true,
+        },
+        // C.LDSP：uimm[5] = [12]，uimm[4:3] = [6:5]，uimm[8:6] = [4:2]
+        (0b10, 0b011) if rd != 0 => Operation::Load {
+            rd,
+            base: 2,
+            offset: (bits(instruction, 12, 12) << 5
+                | bits(instruction, 6, 5) << 3
+                | bits(instruction, 4, 2) << 6) as isize,
+            width: 8,
+            signed: true,
+        },
+        // C.SWSP：uimm[5:2] = [12:9]，uimm[7:6] = [8:7]
+        (0b10, 0b110) => Operation::Store {
+            rs2,
+            base: 2,
+            offset: (bits(instruction, 12, 9) << 2 | bits(instruction, 8, 7) << 6) as isize,
+            width: 4,
+        },
+        // C.SDSP：uimm[5:3] = [12:10]，uimm[8:6] = [9:7]
+        (0b10, 0b111) => Operation::Store {
+            rs2,
+            base: 2,
+            offset: (bits(instruction, 12, 10) << 3 | bits(instruction, 9, 7) << 6) as isize,
+            width: 8,
+        },
+        _ => Operation::Other,
+    }
+}
+
+// 访问陷入之前所在地址空间中的内存
+// 从用户态陷入时临时打开 SUM（允许访问用户页）和 MXR（允许读取只可执行的页），访问完后恢复
+fn with_user_access<T>(context: &Context, f: impl FnOnce() -> T) -> T {
+    let user = context.is_user();
+    let (sum, mxr) = (sstatus::read().sum(), sstatus::read().mxr());
+    if user {
+        unsafe {
+            sstatus::set_sum();
+            sstatus::set_mxr();
+        }
+    }
+    let result = f();
+    unsafe {
+        if user && !sum {
+            sstatus::clear_sum();
+        }
+        if user && !mxr {
+            sstatus::clear_mxr();
+        }
+    }
+    result
+}
+
+// 读取并解码 sepc 处的指令
+// 指令只保证按 2 字节对齐，32 位的指令可能跨页，因此分两次按 16 位读取
+pub fn fetch(context: &Context) -> Instruction {
+    let pc = context.sepc as *const u16;
+    let instruction = with_user_access(context, || unsafe {
+        let low = pc.read_volatile();
+        if length(low) == 2 {
+            low as u32
+        } else {
+            low as u32 | (pc.add(1).read_volatile() as u32) << 16
+        }
+    });
+    decode(instruction)
+}
+
+// 跳过 sepc 处的指令
+pub fn skip(context: &mut Context) {
+    context.sepc += fetch(context).length;
+}
+
+// 模拟的访问由内核代为完成，来自用户态时必须先检查用户态自己能否进行这次访问：
+// 整个区间都要位于用户地址空间中，并且落在带 `USER` 标志、权限允许的段中
+fn check_access(
+    context: &Context,
+    address: usize,
+    width: usize,
+    access: AccessType,
+) -> Result<(), &'static str> {
+    if !context.is_user() {
+        return Ok(());
+    }
+    let end = address.checked_add(width).ok_or("access wraps around")?;
+    if !mapping::user_can_access(VirtualAddress(address)..VirtualAddress(end), access) {
+        return Err("user access to an inaccessible address");
+    }
+    Ok(())
+}
+
+// 用逐字节的访问模拟 sepc 处非对齐的 load / store，完成后跳过这条指令
+// `address` 为 stval 中记录的访问地址。原子指令等无法拆开的访问、用户态无权进行的访问返回错误，
+// 此时上下文保持不变
+pub fn emulate_misaligned(context: &mut Context, address: usize) -> Result<(), &'static str> {
+    let instruction = fetch(context);
+    match instruction.operation {
+        Operation::Load {
+            rd, width, signed, ..
+        } => {
+            check_access(context, address, width, AccessType::Load)?;
+            let mut value = with_user_access(context, || {
+                (0..width).fold(0usize, |value, i| unsafe {
+                    value | ((address as *const u8).add(i).read_volatile() as usize) << (8 * i)
+                })
+            });
+            if signed && width < 8 {
+                let shift = 64 - 8 * width;
+                value = ((value << shift) as isize >> shift) as usize;
+            }
+            // x0 恒为 0
+            if rd != 0 {
+                context.x[rd] = value;
+            }
+        }
+        Operation::Store { rs2, width, .. } => {
+            check_access(context, address, width, AccessType::Store)?;
+            let value = context.x[rs2];
+            with_user_access(context, || {
+                for i in 0..width {
+                    unsafe {
+                        (address as *mut u8)
+                            .add(i)
+                            .write_volatile((value >> (8 * i)) as u8)
+                    };
+                }
+            });
+        }
+        Operation::Other => return Err("cannot emulate misaligned access"),
+    }
+    context.sepc += instruction.length;
+    Ok(())
+}
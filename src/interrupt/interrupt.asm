@@ -3,7 +3,7 @@
 # -------------------------------------------------------------------------
 .altmacro                # 开启宏的替代模式，允许我们在宏里使用循环和变量
 .set    REG_SIZE, 8      # 定义寄存器宽度：64 位 RISC-V 每个寄存器占 8 字节
-.set    CONTEXT_SIZE, 34 # 定义 Context 结构体成员数：32个通用寄存器 + sstatus + sepc
+.set    CONTEXT_SIZE, 36 # 定义 Context 结构体成员数：32个通用寄存器 + sstatus + sepc + kernel_sp + kernel_tp
 
 # 宏 (Macro)：像函数一样的模板，用来减少重复劳动
 # 将指定的寄存器存入栈中对应的位置
@@ -29,18 +29,47 @@
 # -------------------------------------------------------------------------
 # __interrupt: 保存“案发现场”
 # -------------------------------------------------------------------------
+# sscratch 的约定：在内核态运行时为 0；在用户态运行时为这个任务内核栈的栈顶（见 `__restore`）。
+# 陷入时先交换 sp 和 sscratch：得到 0 说明是从内核态陷入的，换回来继续使用当前的栈；
+# 否则 sp 已经是内核栈的栈顶，sscratch 中是用户程序的 sp。
 __interrupt:
-    # 1. 在栈上开辟空间。sp 指针向下移动 34*8 字节，腾出位子放 Context 结构体
-    addi    sp, sp, -34*8
+    csrrw   sp, sscratch, sp
+    bnez    sp, __from_user
+    csrrw   sp, sscratch, sp
+
+    # 0. 检查内核栈是否溢出
+    # 内核栈都位于按 128KB 对齐的槽位的上半部分，下半部分是不映射的保护区域。
+    # 如果放下 Context 之后栈指针的第 16 位为 0，说明它已经落入保护区域，
+    # 此时再往栈上保存寄存器只会再次触发异常、无限递归，必须换到专用的栈上报告错误。
+    # 检查时只能借用 sp 本身，原来的值暂存在 sscratch 中，取回时把 sscratch 重新置 0
+    csrw    sscratch, sp
+    addi    sp, sp, -CONTEXT_SIZE*8
+    srli    sp, sp, 16
+    andi    sp, sp, 1
+    beqz    sp, __stack_overflow
+    csrrw   sp, sscratch, zero
+
+    # 1. 在栈上开辟空间。sp 指针向下移动 CONTEXT_SIZE*8 字节，腾出位子放 Context 结构体
+    addi    sp, sp, -CONTEXT_SIZE*8
 
     # 2. 开始保存通用寄存器
     SAVE    x1, 1        # x1 是返回地址 (ra)
     
     # 特殊处理 sp (x2)：
     # 我们现在的 sp 已经移动过了，但我们要保存的是“发生中断那一刻”的旧 sp
-    addi    x1, sp, 34*8 # 计算旧 sp 的地址
+    addi    x1, sp, CONTEXT_SIZE*8 # 计算旧 sp 的地址
     SAVE    x1, 2        # 将旧 sp 存入偏移量为 2 的位置
+    j       __save_registers
+
+# 从用户态陷入：此时 sp 为内核栈的栈顶，Context 放在栈的最顶端
+__from_user:
+    addi    sp, sp, -CONTEXT_SIZE*8
+    SAVE    x1, 1
+    # 用户程序的 sp 暂存在 sscratch 中，取出后把 sscratch 置 0，表示已经进入内核
+    csrrw   x1, sscratch, zero
+    SAVE    x1, 2
 
+__save_registers:
     # 3. 循环保存 x3 至 x31
     # .rept 29 表示重复执行 29 次，自动保存剩下所有的寄存器
     .set    n, 3
@@ -56,6 +85,13 @@ __interrupt:
     SAVE    s1, 32       # 存入 Context
     SAVE    s2, 33       # 存入 Context
 
+    # 用户程序可能改动了 tp，恢复内核的 tp（hart 编号），它是上次返回用户态时保存在 Context 中的
+    # SPP（第 8 位）为 0 表示从用户态陷入
+    andi    s1, s1, 0x100
+    bnez    s1, 1f
+    LOAD    tp, 35
+1:
+
     # 5. 为跳转到 Rust 的 handle_interrupt 函数准备参数
     # 根据 RISC-V 调用约定：a0, a1, a2 分别存放前三个参数
     # context: &mut Context
@@ -72,6 +108,10 @@ __interrupt:
 # -------------------------------------------------------------------------
 # __restore: 恢复“案发现场”并返回
 # -------------------------------------------------------------------------
+# 进入时 sp 指向要恢复的 Context。
+# 如果要返回用户态（SPP 为 User），Context 必须位于内核栈的最顶端：
+# 把栈顶和当前的 tp 记录在 Context 中，并把栈顶写入 sscratch，下一次陷入时据此切换栈。
+# 新建的用户上下文也从这里进入用户态（见 `interrupt::enter_user`）。
 __restore:
     # 1. 恢复控制状态寄存器 (CSR)
     LOAD    s1, 32
@@ -79,6 +119,14 @@ __restore:
     csrw    sstatus, s1  # 把值写回硬件寄存器
     csrw    sepc, s2     # 这样一会儿 sret 才知道回哪去
 
+    andi    s1, s1, 0x100
+    bnez    s1, 1f
+    addi    s1, sp, CONTEXT_SIZE*8
+    SAVE    s1, 34       # kernel_sp
+    SAVE    tp, 35       # kernel_tp
+    csrw    sscratch, s1
+1:
+
     # 2. 恢复通用寄存器 x1 和 x3~x31
     LOAD    x1, 1
     .set    n, 3
@@ -94,6 +142,25 @@ __restore:
     # 4. 关键指令：sret (Supervisor Return)
     # 这条指令会让 CPU 自动完成以下三件事：
     # 1) 把 pc 设为 sepc 的值。
-    # 2) 恢复 CPU 的特权等级和中断使能状态。
+    # 2) 恢复 CPU 的特权等级（SPP）和中断使能状态（SPIE）。
     # 3) 继续执行原来被中断的代码。
     sret
+
+# -------------------------------------------------------------------------
+# __stack_overflow: 内核栈溢出
+# -------------------------------------------------------------------------
+# 原来的栈已经不能使用，切换到专用的栈上，交给 Rust 报告错误（不会返回）
+__stack_overflow:
+    la      sp, overflow_stack_top
+    mv      a0, tp       # 第一个参数：hart 编号（由 entry.asm 保存在 tp 中）
+    csrr    a1, sscratch # 第二个参数：溢出时的栈指针
+    csrr    a2, sepc     # 第三个参数：溢出时执行的指令地址
+    csrr    a3, stval    # 第四个参数：异常附加信息
+    jal     handle_stack_overflow
+
+    .section .bss
+    .align 12
+# 报告栈溢出专用的栈（16KB）
+overflow_stack:
+    .space 4096 * 4
+overflow_stack_top:
@@ -5,8 +5,25 @@
 // 这告诉 Rust 编译器去寻找同目录下的 handler.rs 和 context.rs 文件
 mod handler;
 mod context;
+mod critical;
+pub mod instruction;
+pub mod plic;
+mod registry;
 mod timer;
 
+#[allow(unused_imports)]
+pub use context::Context;
+#[allow(unused_imports)]
+pub use critical::{
+    InterruptGuard, IrqMutex, IrqMutexGuard, depth, pop_off, push_off, without_interrupts,
+};
+#[allow(unused_imports)]
+pub use handler::{MAX_HARTS, current_hart, enter_user, hart_id};
+#[allow(unused_imports)]
+pub use registry::{PRIORITY_DEFAULT, TrapHandler, TrapResult, TrapSource, register, unregister};
+#[allow(unused_imports)]
+pub use timer::{ticks, time_is_up};
+
 // 初始化中断相关的子模块。
 // 这是整个中断模块的对外总入口。
 // 内部流程：
@@ -0,0 +1,302 @@
+// 平台级中断控制器（PLIC）
+// QEMU virt 平台上的设备（串口、virtio 等）产生的中断都先送到 PLIC，
+// PLIC 再根据每个中断源的优先级、每个上下文（某个 hart 的 M 态或 S 态）的使能位和阈值，
+// 以 SupervisorExternal 中断的形式通知 hart。
+// 内核在外部中断中反复认领（claim）PLIC 上待处理的中断号，交给为这个中断号注册的设备处理函数，
+// 处理完成后再通知 PLIC 完成（complete），直到没有待处理的中断。
+//
+// PLIC 的寄存器（相对于基地址）：
+// - 0x00_0000 + 4 * irq：中断源的优先级，0 表示不会触发
+// - 0x00_2000 + 0x80 * context：上下文的使能位，每个中断源一位
+// - 0x20_0000 + 0x1000 * context：上下文的优先级阈值，只有优先级大于阈值的中断才会送达
+// - 0x20_0004 + 0x1000 * context：读取为认领，写入为完成
+//
+// 基地址、中断源个数和上下文的编号优先取自设备树，没有设备树时按 QEMU virt 的布局：
+// 基地址 0x0c00_0000，hart i 的 S 态上下文为 2 * i + 1。
+
+use super::context::Context;
+use super::critical::IrqMutex;
+use super::handler::{MAX_HARTS, hart_id};
+use super::registry::{self, PRIORITY_DEFAULT, TrapResult, TrapSource};
+use crate::memory::address::*;
+use crate::memory::config::PAGE_SIZE;
+use crate::memory::mapping::{self, Flags, MapType, Segment};
+use core::ptr::{read_volatile, write_volatile};
+use riscv::register::scause::Scause;
+use riscv::register::sie;
+use spin::Once;
+
+// 与 PLIC 兼容的设备树节点
+const COMPATIBLE: [&str; 2] = ["sifive,plic-1.0.0", "riscv,plic0"];
+// 没有设备树时使用的 QEMU virt 布局
+const DEFAULT_BASE: usize = 0x0c00_0000;
+const DEFAULT_SIZE: usize = 0x60_0000;
+const DEFAULT_SOURCES: u32 = 96;
+
+// 各组寄存器的偏移
+const PRIORITY_OFFSET: usize = 0x0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+// 最多支持的中断源个数（中断号 0 保留不用）
+// 用固定大小的数组记录，这样在中断中分发时不需要分配堆内存
+const MAX_SOURCES: usize = 128;
+
+// 设备注册的中断源默认的优先级，阈值为 0，任何大于 0 的优先级都会送达
+pub const IRQ_PRIORITY_DEFAULT: u32 = 1;
+
+// 设备的中断处理函数，参数为中断号
+pub type IrqHandler = fn(u32);
+
+// PLIC 的寄存器位置
+struct Plic {
+    // 寄存器的虚拟地址（线性映射）
+    base: usize,
+    // 中断源的个数，中断号为 1..=sources
+    sources: u32,
+    // 每个 hart 的 S 态上下文编号
+    contexts: [Option<usize>; MAX_HARTS],
+}
+
+static PLIC: Once<Plic> = Once::new();
+
+// 每个中断号上注册的处理函数
+static HANDLERS: IrqMutex<[Option<(&'static str, IrqHandler)>; MAX_SOURCES]> =
+    IrqMutex::new([None; MAX_SOURCES]);
+
+impl Plic {
+    // 从设备树中找到 PLIC，没有设备树时按 QEMU virt 的布局
+    fn probe() -> (PhysicalAddress, usize, Self) {
+        let node = crate::fdt::get().and_then(|fdt| fdt.find_compatible(&COMPATIBLE).next());
+        let Some(node) = node else {
+            let contexts = core::array::from_fn(|hart| Some(2 * hart + 1));
+            return (
+                PhysicalAddress(DEFAULT_BASE),
+                DEFAULT_SIZE,
+                Self::at(DEFAULT_BASE, DEFAULT_SOURCES, contexts),
+            );
+        };
+        let (base, size) = node
+            .reg()
+            .next()
+            .map_or((DEFAULT_BASE, DEFAULT_SIZE), |(base, size)| {
+                (base as usize, size as usize)
+            });
+        let sources = node
+            .property_u64("riscv,ndev")
+            .map_or(DEFAULT_SOURCES, |ndev| ndev as u32);
+        (
+            PhysicalAddress(base),
+            size,
+            Self::at(base, sources, supervisor_contexts(node)),
+        )
+    }
+
+    fn at(base: usize, sources: u32, contexts: [Option<usize>; MAX_HARTS]) -> Self {
+        Self {
+            base: VirtualAddress::from(PhysicalAddress(base)).0,
+            sources: sources.min(MAX_SOURCES as u32 - 1),
+            contexts,
+        }
+    }
+
+    fn register(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.register(offset)) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile(self.register(offset), value) }
+    }
+
+    // 当前 hart 的 S 态上下文
+    fn context(&self) -> usize {
+        self.contexts
+            .get(hart_id())
+            .copied()
+            .flatten()
+            .expect("no PLIC context for this hart")
+    }
+
+    fn set_priority(&self, irq: u32, priority: u32) {
+        self.write(PRIORITY_OFFSET + 4 * irq as usize, priority);
+    }
+
+    fn priority(&self, irq: u32) -> u32 {
+        self.read(PRIORITY_OFFSET + 4 * irq as usize)
+    }
+
+    // 中断源在当前上下文中的使能位所在的寄存器和位
+    fn enable_bit(&self, irq: u32) -> (usize, u32) {
+        let offset = ENABLE_OFFSET + ENABLE_STRIDE * self.context() + 4 * (irq as usize / 32);
+        (offset, 1 << (irq % 32))
+    }
+
+    fn set_enabled(&self, irq: u32, enabled: bool) {
+        let (offset, bit) = self.enable_bit(irq);
+        let value = self.read(offset);
+        self.write(offset, if enabled { value | bit } else { value & !bit });
+    }
+
+    fn is_enabled(&self, irq: u32) -> bool {
+        let (offset, bit) = self.enable_bit(irq);
+        self.read(offset) & bit != 0
+    }
+
+    fn set_threshold(&self, threshold: u32) {
+        self.write(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context(), threshold);
+    }
+
+    // 认领一个待处理的中断，没有时返回 0
+    fn claim(&self) -> u32 {
+        self.read(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context() + 4)
+    }
+
+    // 通知 PLIC 中断已经处理完成
+    fn complete(&self, irq: u32) {
+        self.write(CONTEXT_OFFSET + CONTEXT_STRIDE * self.context() + 4, irq);
+    }
+
+    fn check(&self, irq: u32) -> Result<(), &'static str> {
+        if irq == 0 || irq > self.sources {
+            return Err("invalid IRQ number");
+        }
+        Ok(())
+    }
+}
+
+// 从 PLIC 节点的 `interrupts-extended` 属性中找出每个 hart 的 S 态上下文
+// 属性中依次列出每个上下文对应的 (hart 的中断控制器的 phandle, 中断号)，
+// 中断号为 9（SupervisorExternal）的就是 S 态上下文；中断控制器的父节点是 `cpu@<hart 编号>`。
+fn supervisor_contexts(node: crate::fdt::Node) -> [Option<usize>; MAX_HARTS] {
+    let mut contexts = [None; MAX_HARTS];
+    let fdt = crate::fdt::get().unwrap();
+    let cells = node.property("interrupts-extended").unwrap_or(&[]);
+    for (context, pair) in cells.chunks_exact(8).enumerate() {
+        let phandle = u32::from_be_bytes(pair[..4].try_into().unwrap());
+        let cause = u32::from_be_bytes(pair[4..].try_into().unwrap());
+        if cause != 9 {
+            continue;
+        }
+        let hart = fdt
+            .find_phandle(phandle)
+            .and_then(|intc| intc.parent.split('@').nth(1))
+            .and_then(|unit| usize::from_str_radix(unit, 16).ok());
+        if let Some(slot) = hart.and_then(|hart| contexts.get_mut(hart)) {
+            *slot = Some(context);
+        }
+    }
+    contexts
+}
+
+// 初始化 PLIC：映射寄存器，注册外部中断的处理函数，并为当前 hart 打开外部中断
+// PLIC 的寄存器需要映射到内核的地址空间中，因此在内存模块之后初始化；
+// 外部中断可能发生在任何地址空间中，之后新建的地址空间也都会映射这些寄存器
+pub fn init() {
+    let (base, size, plic) = Plic::probe();
+    let segment = Segment {
+        map_type: MapType::Device,
+        range: VirtualAddress::from(base)
+            ..VirtualAddress::from(base) + size.next_multiple_of(PAGE_SIZE),
+        flags: Flags::READABLE | Flags::WRITABLE,
+    };
+    mapping::map_kernel_device(segment).expect("failed to map PLIC");
+    let plic = PLIC.call_once(|| plic);
+    registry::register(
+        TrapSource::EXTERNAL,
+        PRIORITY_DEFAULT,
+        "plic",
+        supervisor_external,
+    )
+    .unwrap();
+    init_hart();
+    println!(
+        "mod plic initialized: base {:#x}, {} sources",
+        base.0, plic.sources
+    );
+}
+
+// 为当前 hart 打开外部中断，其他 hart 启动时也需要调用
+pub fn init_hart() {
+    let plic = PLIC.get().expect("PLIC is not initialized");
+    plic.set_threshold(0);
+    unsafe { sie::set_sext() };
+}
+
+// 为中断号 `irq` 注册设备的处理函数，并在当前 hart 上打开这个中断源
+pub fn register_irq(irq: u32, name: &'static str, handler: IrqHandler) -> Result<(), &'static str> {
+    let plic = PLIC.get().ok_or("PLIC is not initialized")?;
+    plic.check(irq)?;
+    let mut handlers = HANDLERS.lock();
+    let slot = &mut handlers[irq as usize];
+    if slot.is_some() {
+        return Err("IRQ handler is already registered");
+    }
+    *slot = Some((name, handler));
+    plic.set_priority(irq, IRQ_PRIORITY_DEFAULT);
+    plic.set_enabled(irq, true);
+    Ok(())
+}
+
+// 移除中断号 `irq` 上名为 `name` 的处理函数，并关闭这个中断源
+#[allow(dead_code)]
+pub fn unregister_irq(irq: u32, name: &'static str) -> Result<(), &'static str> {
+    let plic = PLIC.get().ok_or("PLIC is not initialized")?;
+    plic.check(irq)?;
+    let mut handlers = HANDLERS.lock();
+    let slot = &mut handlers[irq as usize];
+    if !slot.is_some_and(|(n, _)| n == name) {
+        return Err("IRQ handler is not registered");
+    }
+    *slot = None;
+    plic.set_enabled(irq, false);
+    plic.set_priority(irq, 0);
+    Ok(())
+}
+
+// 修改中断源的优先级，0 表示屏蔽
+#[allow(dead_code)]
+pub fn set_irq_priority(irq: u32, priority: u32) -> Result<(), &'static str> {
+    let plic = PLIC.get().ok_or("PLIC is not initialized")?;
+    plic.check(irq)?;
+    plic.set_priority(irq, priority);
+    Ok(())
+}
+
+// 中断源的优先级，以及是否在当前 hart 上打开
+#[allow(dead_code)]
+pub fn irq_state(irq: u32) -> Option<(u32, bool)> {
+    let plic = PLIC.get()?;
+    plic.check(irq).ok()?;
+    Some((plic.priority(irq), plic.is_enabled(irq)))
+}
+
+// SupervisorExternal 中断：认领所有待处理的中断，交给各自的处理函数
+// 先复制处理函数再调用，调用时不持有锁，处理函数中可以注册或移除处理函数
+fn supervisor_external(_: &mut Context, _: Scause, _: usize) -> TrapResult {
+    let Some(plic) = PLIC.get() else {
+        return TrapResult::Pass;
+    };
+    loop {
+        let irq = plic.claim();
+        if irq == 0 {
+            break;
+        }
+        let entry = HANDLERS.lock().get(irq as usize).copied().flatten();
+        match entry {
+            Some((_, handler)) => handler(irq),
+            // 没有处理函数的中断源会不断触发，直接关掉
+            None => {
+                println!("unhandled IRQ {}, disabled", irq);
+                plic.set_enabled(irq, false);
+            }
+        }
+        plic.complete(irq);
+    }
+    TrapResult::Handled
+}
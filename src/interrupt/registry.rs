@@ -0,0 +1,188 @@
+// 中断和异常处理函数的注册表
+// 驱动和各个子系统可以为某一种异常（按 scause 中的异常号）或某一种中断（软件、时钟、外部）
+// 注册处理函数，不需要修改 `handler.rs`。
+//
+// 发生陷入时按以下顺序调用处理函数，直到某个处理函数返回 [`TrapResult::Handled`]：
+// 1. 为这种异常 / 中断注册的处理函数，按优先级从小到大；
+// 2. 为所有异常（[`TrapSource::AnyException`]）或所有中断（[`TrapSource::AnyInterrupt`]）注册的处理函数；
+// 3. 都没有处理时，由 `handler.rs` 中的 `fault` 报告错误。
+// 优先级相同的处理函数按注册顺序调用。
+
+use super::context::Context;
+use super::critical::IrqMutex;
+use riscv::register::scause::{Exception, Interrupt, Scause};
+
+// 内核自带的处理函数使用的优先级，驱动可以用更小的值排在它们前面
+pub const PRIORITY_DEFAULT: u8 = 128;
+
+// 最多能注册的处理函数个数
+// 用固定大小的数组记录，这样分发时不需要分配堆内存
+const MAX_HANDLERS: usize = 32;
+
+// 处理函数的结果
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TrapResult {
+    // 已经处理，不再调用后面的处理函数
+    Handled,
+    // 不是自己负责的情况，交给后面的处理函数
+    Pass,
+}
+
+// 处理函数，参数与 `handle_interrupt` 相同：上下文、scause 和 stval
+pub type TrapHandler = fn(&mut Context, Scause, usize) -> TrapResult;
+
+// 处理函数所处理的陷入
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TrapSource {
+    // 某一种异常，值为 scause 中的异常号
+    Exception(usize),
+    // 某一种中断，值为 scause 中的中断号
+    Interrupt(usize),
+    // 所有的异常，在对应的处理函数都没有处理时调用
+    AnyException,
+    // 所有的中断，在对应的处理函数都没有处理时调用
+    AnyInterrupt,
+}
+
+#[allow(dead_code)]
+impl TrapSource {
+    // 软件中断（核间中断）
+    pub const SOFTWARE: Self = Self::Interrupt(1);
+    // 时钟中断
+    pub const TIMER: Self = Self::Interrupt(5);
+    // 外部中断（来自 PLIC）
+    pub const EXTERNAL: Self = Self::Interrupt(9);
+
+    // 某一次陷入对应的具体来源
+    fn of(scause: Scause) -> Self {
+        if scause.is_interrupt() {
+            Self::Interrupt(scause.code())
+        } else {
+            Self::Exception(scause.code())
+        }
+    }
+
+    // 具体来源对应的兜底来源
+    fn fallback(self) -> Self {
+        match self {
+            Self::Interrupt(_) | Self::AnyInterrupt => Self::AnyInterrupt,
+            Self::Exception(_) | Self::AnyException => Self::AnyException,
+        }
+    }
+}
+
+impl From<Exception> for TrapSource {
+    fn from(exception: Exception) -> Self {
+        Self::Exception(match exception {
+            Exception::InstructionMisaligned => 0,
+            Exception::InstructionFault => 1,
+            Exception::IllegalInstruction => 2,
+            Exception::Breakpoint => 3,
+            Exception::LoadFault => 5,
+            Exception::StoreMisaligned => 6,
+            Exception::StoreFault => 7,
+            Exception::UserEnvCall => 8,
+            Exception::InstructionPageFault => 12,
+            Exception::LoadPageFault => 13,
+            Exception::StorePageFault => 15,
+            Exception::Unknown => usize::MAX,
+        })
+    }
+}
+
+impl From<Interrupt> for TrapSource {
+    fn from(interrupt: Interrupt) -> Self {
+        Self::Interrupt(match interrupt {
+            Interrupt::UserSoft => 0,
+            Interrupt::SupervisorSoft => 1,
+            Interrupt::UserTimer => 4,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::UserExternal => 8,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::Unknown => usize::MAX,
+        })
+    }
+}
+
+// 注册表中的一项
+#[derive(Copy, Clone)]
+struct Entry {
+    source: TrapSource,
+    priority: u8,
+    name: &'static str,
+    handler: TrapHandler,
+}
+
+// 按优先级排列的处理函数
+// 分发中断时也要获取这把锁，因此持有锁时关闭中断
+static HANDLERS: IrqMutex<[Option<Entry>; MAX_HANDLERS]> = IrqMutex::new([None; MAX_HANDLERS]);
+
+// 注册一个处理函数，`priority` 越小越先调用
+// 同一种陷入上不能有两个同名的处理函数
+pub fn register(
+    source: impl Into<TrapSource>,
+    priority: u8,
+    name: &'static str,
+    handler: TrapHandler,
+) -> Result<(), &'static str> {
+    let source = source.into();
+    let mut handlers = HANDLERS.lock();
+    let length = handlers.iter().take_while(|entry| entry.is_some()).count();
+    if handlers[..length]
+        .iter()
+        .flatten()
+        .any(|entry| entry.source == source && entry.name == name)
+    {
+        return Err("trap handler is already registered");
+    }
+    if length == MAX_HANDLERS {
+        return Err("too many trap handlers");
+    }
+    // 插在所有优先级不大于它的处理函数之后
+    let index = handlers[..length]
+        .iter()
+        .flatten()
+        .take_while(|entry| entry.priority <= priority)
+        .count();
+    handlers.copy_within(index..length, index + 1);
+    handlers[index] = Some(Entry {
+        source,
+        priority,
+        name,
+        handler,
+    });
+    Ok(())
+}
+
+// 移除某种陷入上名为 `name` 的处理函数
+#[allow(dead_code)]
+pub fn unregister(source: impl Into<TrapSource>, name: &'static str) -> Result<(), &'static str> {
+    let source = source.into();
+    let mut handlers = HANDLERS.lock();
+    let index = handlers
+        .iter()
+        .position(|entry| entry.is_some_and(|entry| entry.source == source && entry.name == name))
+        .ok_or("trap handler is not registered")?;
+    handlers.copy_within(index + 1.., index);
+    handlers[MAX_HANDLERS - 1] = None;
+    Ok(())
+}
+
+// 依次调用处理函数，返回是否有处理函数处理了这次陷入
+// 先复制一份注册表，调用时不持有锁：处理函数中再次陷入（例如缺页）或者注册新的处理函数都不会死锁
+pub fn dispatch(context: &mut Context, scause: Scause, stval: usize) -> TrapResult {
+    let handlers = *HANDLERS.lock();
+    let source = TrapSource::of(scause);
+    for target in [source, source.fallback()] {
+        for entry in handlers
+            .iter()
+            .flatten()
+            .filter(|entry| entry.source == target)
+        {
+            if (entry.handler)(context, scause, stval) == TrapResult::Handled {
+                return TrapResult::Handled;
+            }
+        }
+    }
+    TrapResult::Pass
+}
@@ -3,19 +3,32 @@
 
 use crate::sbi::set_timer; // 调用 sbi.rs 里的设置定时器功能
 use riscv::register::{time, sie, sstatus}; // 引入 RISC-V 核心寄存器操作
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// 每秒的时钟中断次数
+const TICKS_PER_SECOND: usize = 100;
 
 // 1. 时钟中断的间隔
-// 这里的 100000 是 CPU 的时钟周期数。
-// 比如 CPU 频率是 10MHz，那么每秒会发生 100 次中断（10,000,000 / 100,000）。
-static INTERVAL: usize = 100000;
+// 这里的 100000 是 `time` 寄存器的计数周期数。
+// 比如时钟频率是 10MHz，那么每秒会发生 100 次中断（10,000,000 / 100,000）。
+// 初始化时根据设备树中的 timebase-frequency 重新计算，没有设备树时使用这个默认值。
+static INTERVAL: AtomicUsize = AtomicUsize::new(100000);
 
 // 2. 触发时钟中断计数
-// static mut 表示这是一个全局可变的变量，用来记录系统启动以来跳动了多少次。
-pub static mut TICKS: usize = 0;
+// 记录系统启动以来跳动了多少次。中断处理函数和普通代码都会访问它，因此使用原子变量。
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+// 3. 是否到了关机的时间
+// 关机前要打印堆的报告，报告需要获取堆内部的锁，而被时钟中断打断的代码可能正持有它们，
+// 因此中断中只置位，由 `rust_main` 的主循环在中断上下文之外打印报告并关机。
+static TIME_UP: AtomicBool = AtomicBool::new(false);
 
 // 初始化时钟中断
 // 开启硬件开关
 pub fn init() {
+    if let Some(frequency) = crate::fdt::get().and_then(|fdt| fdt.timebase_frequency()) {
+        INTERVAL.store(frequency as usize / TICKS_PER_SECOND, Ordering::Relaxed);
+    }
     unsafe {
         // 开启 STIE (Supervisor Timer Interrupt Enable)
         // 告诉硬件：我想要接收来自定时器的中断信号。
@@ -33,28 +46,36 @@ pub fn init() {
 // 计算公式：下一次响铃时间 = 当前时间 + 固定的间隔。
 fn set_next_timeout() {
     // time::read() 读取 RISC-V 硬件寄存器 `time` 的当前值。
-    set_timer(time::read() + INTERVAL);
+    set_timer(time::read() + INTERVAL.load(Ordering::Relaxed));
 }
 
 // 每一次时钟中断时调用的业务逻辑
 // 这个函数通常会被 `handle_interrupt` 调用。
+#[allow(clippy::manual_is_multiple_of)]
 pub fn tick() {
     // 1. 极其重要：必须预约下一次中断，否则闹钟就变成“一次性”的了
     set_next_timeout();
-    unsafe {
-        TICKS += 1;
-        // 2. 计数器自增
-        let current_ticks = TICKS;
-        
-        // 3. 为了不让屏幕被刷屏，我们每隔 100 次打印一次
-        if current_ticks % 100 == 0 {
-            println!("{} tick", current_ticks);
-        }
-
-        // 4. 当心跳达到 500 次（大约 5 秒）时自动关机
-        if current_ticks >= 500 {
-            println!("Time's up! Shutting down...");
-            crate::sbi::shutdown(); // 直接调用 sbi 模块里的关机函数
-        }
+    // 2. 计数器自增
+    let current_ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    // 3. 为了不让屏幕被刷屏，我们每隔 100 次打印一次
+    if current_ticks % 100 == 0 {
+        println!("{} tick", current_ticks);
     }
+
+    // 4. 当心跳达到 500 次（大约 5 秒）时通知主循环关机
+    if current_ticks >= 500 && !TIME_UP.swap(true, Ordering::Relaxed) {
+        println!("Time's up! Shutting down...");
+    }
+}
+
+// 是否到了关机的时间
+pub fn time_is_up() -> bool {
+    TIME_UP.load(Ordering::Relaxed)
+}
+
+// 系统启动以来的时钟中断次数
+#[allow(dead_code)]
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
 }
@@ -13,6 +13,8 @@
 
 // 开启对分配错误处理器的支持（对应 heap.rs 里的 #[alloc_error_handler]）
 #![feature(alloc_error_handler)]
+// 允许为自定义的页号类型实现 Step，从而可以用 `start..end` 逐页迭代
+#![feature(step_trait)]
 
 // 在 no_std 下使用 Box/Vec 必须手动声明这个 crate
 extern crate alloc;
@@ -30,6 +32,8 @@ mod panic; // 引入我们写的 panic 处理逻辑，后面不再用`core::pani
 mod sbi;   // 引入 SBI 服务调用
 mod interrupt;
 mod memory;
+mod fdt;
+mod syscall;
 
 // 3. 嵌入之前写好的 entry.asm。
 // 这样编译器就会把汇编代码拼接到生成的二进制文件中。
@@ -90,14 +94,810 @@ fn test_heap() {
     println!("Heap test passed! (Allocated and verified 10000 items)");
 }
 
+// 堆统计测试函数
+fn test_heap_stats() {
+    use alloc::boxed::Box;
+    let before = memory::heap::stats();
+    let v = Box::new([0u8; 100]);
+    let during = memory::heap::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.live_bytes, before.live_bytes + 100);
+    assert!(during.peak_bytes >= during.live_bytes);
+    drop(v);
+    let after: memory::heap::HeapStats = memory::heap::stats();
+    assert_eq!(after.live_bytes, before.live_bytes);
+    assert_eq!(after.live_allocations(), before.live_allocations());
+    println!(
+        "Heap stats test passed! ({} live blocks, peak {:#x} bytes)",
+        after.live_allocations(),
+        after.peak_bytes
+    );
+}
+
+// slab 对象缓存测试函数
+fn test_slab() {
+    use alloc::vec::Vec;
+    use memory::heap::{CacheBox, ObjectCache};
+    static CACHE: ObjectCache<[u64; 5]> = ObjectCache::new("test");
+    // 分配足够多的对象，需要用到多个 slab
+    let objects: Vec<CacheBox<[u64; 5]>> = (0..200)
+        .map(|i| CACHE.alloc([i; 5]).unwrap())
+        .collect();
+    let stats = CACHE.stats();
+    assert_eq!(stats.object_size, 40);
+    assert_eq!(stats.objects_in_use, 200);
+    assert!(stats.slabs > 1 && stats.slabs * stats.slab_size >= 200 * 40);
+    for (i, object) in objects.iter().enumerate() {
+        assert_eq!(**object, [i as u64; 5]);
+    }
+    drop(objects);
+    // 对象全部释放后，空的 slab 可以还给堆
+    assert_eq!(CACHE.stats().objects_in_use, 0);
+    assert!(CACHE.reclaim() > 0);
+    assert_eq!(CACHE.stats().slabs, 0);
+    println!("Slab test passed! ({} slabs of {:#x} bytes)", stats.slabs, stats.slab_size);
+}
+
+// 堆扩展测试函数
+fn test_heap_growth() {
+    use alloc::vec::Vec;
+    let size = memory::heap::size();
+    // 申请一块比当前整个堆还大的空间，堆必须先扩展才能满足
+    let mut vec: Vec<u8> = Vec::with_capacity(size);
+    vec.resize(size, 0xab);
+    assert!(memory::heap::size() > size);
+    assert!(vec.iter().all(|&byte| byte == 0xab));
+    drop(vec);
+    // 释放后，借来的空间可以还回去
+    let released = memory::heap::shrink();
+    assert!(released > 0);
+    println!(
+        "Heap growth test passed! (grew past {:#x} bytes, released {:#x} bytes)",
+        size, released
+    );
+}
+
+// 可失败分配测试函数
+fn test_fallible_alloc() {
+    use alloc::vec::Vec;
+    use core::alloc::Layout;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use memory::heap::{self, TryVec};
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn count_calls(_: &Layout) -> usize {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        0
+    }
+    // 内存充足时与不可失败的接口相同
+    let value = heap::try_box([7u64; 4]).unwrap();
+    assert_eq!(*value, [7; 4]);
+    let mut vec: Vec<usize> = heap::try_vec_with_capacity(4).unwrap();
+    vec.try_push(1).unwrap();
+    vec.try_extend(2..5).unwrap();
+    assert_eq!(vec, [1, 2, 3, 4]);
+    // 超过堆上限的请求：OOM 策略依次调用各个回收函数，都回收不到内存时返回错误而不是 panic
+    heap::register_oom_handler("test", count_calls).unwrap();
+    let before = heap::oom_stats();
+    assert_eq!(
+        heap::try_zeroed_buffer(memory::config::KERNEL_HEAP_MAX_SIZE).unwrap_err(),
+        heap::OUT_OF_MEMORY
+    );
+    assert!(vec.try_reserve_additional(usize::MAX / 2).is_err());
+    let after = heap::oom_stats();
+    assert!(after.invocations > before.invocations);
+    assert_eq!(after.recoveries, before.recoveries);
+    assert!(CALLS.load(Ordering::Relaxed) > 0);
+    heap::unregister_oom_handler("test").unwrap();
+    // 失败的分配不影响之后的分配
+    assert_eq!(heap::try_zeroed_buffer(100).unwrap(), [0; 100]);
+    // 持有帧分配器的锁时堆需要扩展：扩展和回收都不能等待这把锁，分配直接失败而不是死锁
+    let size = heap::size();
+    let frame_allocator = memory::frame::FRAME_ALLOCATOR.lock();
+    assert!(heap::try_zeroed_buffer(size).is_err());
+    drop(frame_allocator);
+    assert_eq!(heap::try_zeroed_buffer(size).unwrap().len(), size);
+    heap::shrink();
+    println!(
+        "Fallible allocation test passed! ({} OOM invocations, {:#x} bytes reclaimed)",
+        after.invocations, after.reclaimed_bytes
+    );
+}
+
+// 堆地址检查测试函数（`heap_sanitizer` 功能）
+// 这里只验证正常使用时检查能够通过；越界写入等错误会直接 panic
+#[cfg(feature = "heap_sanitizer")]
+fn test_heap_sanitizer() {
+    use alloc::vec::Vec;
+    use memory::heap::{self, sanitizer};
+    let mut buffer: Vec<u8> = Vec::with_capacity(100);
+    buffer.resize(100, 0x5a);
+    assert!(sanitizer::check() > 0);
+    drop(buffer);
+    // 释放的内存块进入隔离区，毒值完好
+    let stats = sanitizer::stats();
+    assert!(stats.quarantined_blocks > 0 && stats.quarantined_bytes > 100);
+    sanitizer::check();
+    // 收缩堆时隔离区被清空
+    heap::shrink();
+    assert_eq!(sanitizer::stats().quarantined_blocks, 0);
+    println!(
+        "Heap sanitizer test passed! ({} live blocks, {} checks)",
+        stats.live_blocks,
+        sanitizer::stats().checks
+    );
+}
+
+// 物理页分配测试函数
+fn test_frame() {
+    use memory::frame::FRAME_ALLOCATOR;
+    for _ in 0..2 {
+        // 注意：alloc() 返回后锁就被释放了，FrameTracker 在 drop 时还需要再次获取这把锁
+        let mut frame_0 = FRAME_ALLOCATOR.lock().alloc().unwrap();
+        let frame_1 = FRAME_ALLOCATOR.lock().alloc().unwrap();
+        // 分配出的页可以直接读写
+        frame_0[0] = 0xab;
+        assert_eq!(frame_0[0], 0xab);
+        assert_ne!(frame_0.address(), frame_1.address());
+        println!("{} and {}", frame_0.address(), frame_1.address());
+        // 离开作用域时两个页自动归还，第二轮应当分配到相同的地址
+    }
+    println!("Frame test passed!");
+}
+
+// DMA 缓冲区测试函数
+fn test_dma() {
+    use memory::address::{PhysicalAddress, VirtualAddress, VirtualPageNumber};
+    use memory::config::PAGE_SIZE;
+    use memory::dma::DmaBuffer;
+    use memory::frame::FRAME_ALLOCATOR;
+    let free = FRAME_ALLOCATOR.lock().free_frames();
+    let mut buffer = DmaBuffer::with_size(3 * PAGE_SIZE - 1).unwrap();
+    assert_eq!(buffer.size(), 3 * PAGE_SIZE);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free - 3);
+    // 内核通过线性映射写入，设备看到的是对应的物理地址
+    assert!(buffer.iter().all(|&byte| byte == 0));
+    buffer[PAGE_SIZE] = 0xab;
+    let pa = buffer.physical_address_at(PAGE_SIZE).unwrap();
+    assert_eq!(pa.0 % PAGE_SIZE, 0);
+    let byte = unsafe { *VirtualAddress::from(pa).deref::<u8>() };
+    assert_eq!(byte, 0xab);
+    // 只有线性映射区域中的虚拟地址才能换算回物理地址
+    assert_eq!(PhysicalAddress::try_from(buffer.virtual_address()), Ok(buffer.physical_address()));
+    assert!(PhysicalAddress::try_from(VirtualAddress(0x1000)).is_err());
+    // 按页对齐的地址才能直接转换为页号
+    assert_eq!(VirtualPageNumber::try_from(VirtualAddress(0x3000)), Ok(VirtualPageNumber(3)));
+    assert!(VirtualPageNumber::try_from(VirtualAddress(0x3001)).is_err());
+    // 按 16 页对齐
+    let aligned = DmaBuffer::with_alignment(2, 16).unwrap();
+    assert_eq!(aligned.physical_address().0 % (16 * PAGE_SIZE), 0);
+    println!("DMA test passed! ({} at {})", buffer.size(), buffer.physical_address());
+    drop(buffer);
+    drop(aligned);
+    assert_eq!(FRAME_ALLOCATOR.lock().free_frames(), free);
+}
+
+// 地址空间测试函数
+fn test_memory_set() {
+    use memory::address::VirtualAddress;
+    use memory::mapping::{Flags, MapType, MemorySet, Segment};
+    // 以内核地址空间为基础，在低半部分加入一个按帧分配的段
+    let mut memory_set = MemorySet::new_kernel().unwrap();
+    let segment = Segment {
+        map_type: MapType::Framed,
+        range: VirtualAddress(0x1000_0ff0)..VirtualAddress(0x1000_3000),
+        flags: Flags::READABLE | Flags::WRITABLE,
+    };
+    // 初始数据跨越两个页，需要逐页写入
+    memory_set.add_segment(segment.clone(), Some(b"hello, memory set")).unwrap();
+    let pa = memory_set.mapping.translate(VirtualAddress(0x1000_1000)).unwrap();
+    assert_eq!(unsafe { *pa.deref_kernel::<u8>() }, b't');
+    // 重复添加同一区域应当失败
+    assert!(memory_set.add_segment(segment.clone(), None).is_err());
+    memory_set.remove_segment(&segment).unwrap();
+    assert!(memory_set.mapping.translate(VirtualAddress(0x1000_1000)).is_none());
+    println!("MemorySet test passed!");
+}
+
+// 物理页耗尽时添加段的测试函数
+fn test_segment_rollback() {
+    use alloc::vec::Vec;
+    use memory::address::VirtualAddress;
+    use memory::frame::FRAME_ALLOCATOR;
+    use memory::mapping::{Flags, MapType, MemorySet, Segment};
+    let mut memory_set = MemorySet::new_kernel().unwrap();
+    let segment = Segment {
+        map_type: MapType::Framed,
+        range: VirtualAddress(0x3000_0000)..VirtualAddress(0x3000_8000),
+        flags: Flags::READABLE | Flags::WRITABLE,
+    };
+    // 先预留记录物理页的空间，占用物理页时不会再触发堆的扩张
+    let free = FRAME_ALLOCATOR.lock().free_frames();
+    let mut held = Vec::with_capacity(free);
+    // 只留下 5 个物理页：2 个用于中间页表，3 个用于段中的页，映射到第 4 页时失败
+    while FRAME_ALLOCATOR.lock().free_frames() > 5 {
+        held.push(FRAME_ALLOCATOR.lock().alloc().unwrap());
+    }
+    assert!(memory_set.add_segment(segment.clone(), None).is_err());
+    // 已经建立的映射被撤销，除了中间页表之外的物理页都已经归还
+    for page in 0..8 {
+        let va = VirtualAddress(0x3000_0000 + page * 0x1000);
+        assert!(memory_set.mapping.translate(va).is_none());
+    }
+    assert!(FRAME_ALLOCATOR.lock().free_frames() >= 3);
+    // 归还物理页之后同一区域可以正常添加
+    drop(held);
+    memory_set.add_segment(segment.clone(), None).unwrap();
+    memory_set.remove_segment(&segment).unwrap();
+    println!("Segment rollback test passed!");
+}
+
+// 按需分配测试函数
+fn test_demand_paging() {
+    use alloc::sync::Arc;
+    use memory::address::VirtualAddress;
+    use memory::mapping::{self, AccessType, Flags, MapType, MemorySet, Privilege, Segment};
+    use spin::Mutex;
+    let memory_set = Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+    // 预留 4 个页，但不分配物理页
+    let segment = Segment {
+        map_type: MapType::Lazy,
+        range: VirtualAddress(0x2000_0000)..VirtualAddress(0x2000_4000),
+        flags: Flags::READABLE | Flags::WRITABLE,
+    };
+    memory_set.lock().add_segment(segment, None).unwrap();
+    assert!(memory_set.lock().mapping.translate(VirtualAddress(0x2000_2000)).is_none());
+    // 切换到新的地址空间后直接访问，缺页异常会为这一页分配物理页
+    mapping::activate(memory_set.clone());
+    let (minor_faults, major_faults) = (mapping::stats().minor, mapping::stats().major);
+    let pointer = 0x2000_2008 as *mut usize;
+    unsafe {
+        // 新分配的页应当是全零的
+        assert_eq!(pointer.read_volatile(), 0);
+        pointer.write_volatile(0xdead_beef);
+        assert_eq!(pointer.read_volatile(), 0xdead_beef);
+    }
+    assert_eq!(mapping::stats().minor, minor_faults + 1);
+    assert_eq!(mapping::stats().major, major_faults);
+    // 切换回内核的地址空间
+    mapping::activate(mapping::KERNEL_MEMORY_SET.clone());
+    assert!(memory_set.lock().mapping.translate(VirtualAddress(0x2000_2000)).is_some());
+    // 用户页只允许用户态访问，内核态需要打开 SUM；页已经存在时也同样检查
+    let user_segment = Segment {
+        map_type: MapType::Lazy,
+        range: VirtualAddress(0x2001_0000)..VirtualAddress(0x2001_1000),
+        flags: Flags::READABLE | Flags::WRITABLE | Flags::USER,
+    };
+    memory_set.lock().add_segment(user_segment, None).unwrap();
+    let fault = |va: usize, privilege: Privilege| {
+        memory_set.lock().handle_page_fault(VirtualAddress(va), AccessType::Load, privilege)
+    };
+    assert!(fault(0x2001_0000, Privilege::Supervisor { sum: false }).is_err());
+    assert!(fault(0x2001_0000, Privilege::User).is_ok());
+    assert!(fault(0x2001_0000, Privilege::Supervisor { sum: false }).is_err());
+    assert!(fault(0x2001_0000, Privilege::Supervisor { sum: true }).is_ok());
+    assert!(fault(0x2000_2000, Privilege::User).is_err());
+    let stats = mapping::stats();
+    println!(
+        "Demand paging test passed! (minor faults: {}, major faults: {}, invalid faults: {})",
+        stats.minor, stats.major, stats.invalid
+    );
+}
+
+// 大页测试函数
+fn test_huge_pages() {
+    use memory::address::*;
+    use memory::config::PAGE_SIZE;
+    use memory::mapping::{Flags, MapType, MemorySet, Segment};
+    let mut memory_set = MemorySet::new_kernel().unwrap();
+    let mapping = &mut memory_set.mapping;
+    // 内核之后的物理内存使用 2MB 的大页线性映射
+    let va = VirtualAddress::from(PhysicalAddress(0x8600_0000));
+    let vpn = VirtualPageNumber::floor(va);
+    assert_eq!(mapping.page_size(vpn), Some(0x20_0000));
+    assert_eq!(mapping.translate(va + 0x1234), Some(PhysicalAddress(0x8600_1234)));
+    // 只修改其中一页的权限，大页被拆分，其余页的权限不变
+    let tables = mapping.table_count();
+    mapping.update_flags(vpn + 1, Flags::READABLE).unwrap();
+    assert_eq!(mapping.table_count(), tables + 1);
+    assert_eq!(mapping.page_size(vpn + 1), Some(PAGE_SIZE));
+    assert_eq!(mapping.lookup(vpn + 1).unwrap().flags() & Flags::WRITABLE, Flags::empty());
+    assert!(mapping.lookup(vpn).unwrap().flags().contains(Flags::WRITABLE));
+    assert_eq!(mapping.translate(va + PAGE_SIZE), Some(PhysicalAddress(0x8600_1000)));
+    // 设备映射同样在对齐时使用大页，移除后整个大页被取消映射
+    let segment = Segment {
+        map_type: MapType::Device,
+        range: VirtualAddress::from(PhysicalAddress(0x4000_0000))
+            ..VirtualAddress::from(PhysicalAddress(0x8000_0000)),
+        flags: Flags::READABLE | Flags::WRITABLE,
+    };
+    memory_set.add_segment(segment.clone(), None).unwrap();
+    let device = VirtualPageNumber::floor(segment.range.start);
+    assert_eq!(memory_set.mapping.page_size(device), Some(0x4000_0000));
+    memory_set.remove_segment(&segment).unwrap();
+    assert_eq!(memory_set.mapping.page_size(device), None);
+    println!("Huge page test passed!");
+}
+
+// ASID 测试函数
+fn test_asid() {
+    use alloc::sync::Arc;
+    use memory::address::{VirtualAddress, VirtualPageNumber};
+    use memory::mapping::{self, MemorySet};
+    use riscv::register::satp;
+    use spin::Mutex;
+    let first = Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+    let second = Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+    // 每个地址空间有自己的 ASID，再次激活时保持不变
+    mapping::activate(first.clone());
+    let first_asid = satp::read().asid();
+    mapping::activate(second.clone());
+    let second_asid = satp::read().asid();
+    mapping::activate(first.clone());
+    assert_eq!(satp::read().asid(), first_asid);
+    if mapping::asid_count() > 2 {
+        assert_ne!(first_asid, second_asid);
+    }
+    // 修改当前地址空间的映射（拆分大页）后刷新 TLB，数据仍然可以通过新的页表项读到
+    let frame = memory::frame::FRAME_ALLOCATOR.lock().alloc().unwrap();
+    let va = VirtualAddress::from(frame.address());
+    let pointer = va.0 as *mut usize;
+    unsafe { pointer.write_volatile(0x5a5a) };
+    let vpn = VirtualPageNumber::floor(va);
+    first.lock().mapping.update_flags(vpn, mapping::Flags::READABLE).unwrap();
+    assert_eq!(unsafe { pointer.read_volatile() }, 0x5a5a);
+    mapping::activate(mapping::KERNEL_MEMORY_SET.clone());
+    println!(
+        "ASID test passed! (asids: {}, {} of {})",
+        first_asid,
+        second_asid,
+        mapping::asid_count()
+    );
+}
+
+// 内核栈测试函数
+fn test_kernel_stack() {
+    use memory::mapping::{self, KernelStack, MemorySet, KERNEL_MEMORY_SET};
+    // 启动栈下方的保护区域没有映射
+    let guard = mapping::boot_stack_guard_range();
+    assert!(mapping::is_guard_page(guard.start));
+    assert!(KERNEL_MEMORY_SET.lock().mapping.translate(guard.start).is_none());
+    // 新分配的内核栈整个都可以读写，紧挨着栈底的就是保护区域
+    let before = MemorySet::new_kernel().unwrap();
+    let stack = KernelStack::new().unwrap();
+    let range = stack.range();
+    unsafe {
+        ((stack.top() - 8).0 as *mut usize).write_volatile(0x1234);
+        (range.start.0 as *mut usize).write_volatile(0x5678);
+    }
+    assert!(mapping::is_guard_page(range.start - 1));
+    assert!(!mapping::is_guard_page(range.start));
+    assert!(KERNEL_MEMORY_SET.lock().mapping.translate(range.start - 1).is_none());
+    // 从用户态陷入时不切换地址空间，因此栈在之前和之后创建的地址空间中都映射到同样的物理页
+    let after = MemorySet::new_kernel().unwrap();
+    let physical = KERNEL_MEMORY_SET.lock().mapping.translate(range.start);
+    assert!(physical.is_some());
+    assert_eq!(before.mapping.translate(range.start), physical);
+    assert_eq!(after.mapping.translate(range.start), physical);
+    // 释放后栈所在的页在所有地址空间中都不再映射
+    drop(stack);
+    assert!(KERNEL_MEMORY_SET.lock().mapping.translate(range.start).is_none());
+    assert!(before.mapping.translate(range.start).is_none());
+    assert!(after.mapping.translate(range.start).is_none());
+    println!("Kernel stack test passed! (boot stack guard at {})", guard.start);
+}
+
+// 在一个新建的地址空间中运行测试：激活它并打开 SUM，让内核可以直接访问其中的用户页
+// 离开时（包括提前返回）关闭 SUM 并切换回内核的地址空间
+fn with_user_space<T>(
+    f: impl FnOnce(&alloc::sync::Arc<spin::Mutex<memory::mapping::MemorySet>>) -> T,
+) -> T {
+    use alloc::sync::Arc;
+    use memory::mapping::{self, MemorySet};
+    use spin::Mutex;
+    struct Restore;
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            unsafe { riscv::register::sstatus::clear_sum() };
+            mapping::activate(mapping::KERNEL_MEMORY_SET.clone());
+        }
+    }
+    let memory_set = Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+    mapping::activate(memory_set.clone());
+    let _restore = Restore;
+    // 内核访问用户页需要打开 SUM
+    unsafe { riscv::register::sstatus::set_sum() };
+    f(&memory_set)
+}
+
+// mmap 系统调用测试函数
+fn test_mmap() {
+    use memory::config::{PAGE_SIZE, USER_MMAP_BASE};
+    use syscall::*;
+    const RW: usize = 0x3;
+    const R: usize = 0x1;
+    const PRIVATE_ANONYMOUS: usize = 0x22;
+    const FIXED: usize = 0x10;
+    const FIXED_NOREPLACE: usize = 0x10_0000;
+    with_user_space(|memory_set| {
+        let segments = || memory_set.lock().segments.len();
+        let kernel_segments = segments();
+        // 不指定地址时，从 USER_MMAP_BASE 开始分配
+        let address = syscall(SYS_MMAP, [0, 3 * PAGE_SIZE, RW, PRIVATE_ANONYMOUS, 0, 0]);
+        assert_eq!(address as usize, USER_MMAP_BASE.0);
+        let page = |i: usize| (address as usize + i * PAGE_SIZE) as *mut usize;
+        unsafe { page(1).write_volatile(0x1234) };
+        // 修改中间一页的权限，段被切成三段；改回来后重新合并
+        assert_eq!(syscall(SYS_MPROTECT, [page(1) as usize, PAGE_SIZE, R, 0, 0, 0]), 0);
+        assert_eq!(segments(), kernel_segments + 3);
+        assert_eq!(syscall(SYS_MPROTECT, [page(1) as usize, PAGE_SIZE, 0, 0, 0, 0]), 0);
+        assert_eq!(syscall(SYS_MPROTECT, [page(1) as usize, PAGE_SIZE, RW, 0, 0, 0]), 0);
+        assert_eq!(segments(), kernel_segments + 1);
+        // 暂时取消所有权限期间，物理页中的数据仍然保留
+        assert_eq!(unsafe { page(1).read_volatile() }, 0x1234);
+        // 解除第一页的映射后，剩下的两页仍然可用
+        assert_eq!(syscall(SYS_MUNMAP, [address as usize, PAGE_SIZE, 0, 0, 0, 0]), 0);
+        assert_eq!(segments(), kernel_segments + 1);
+        assert_eq!(unsafe { page(1).read_volatile() }, 0x1234);
+        // 错误的参数返回错误码
+        let noreplace = PRIVATE_ANONYMOUS | FIXED_NOREPLACE;
+        let overlapping = [page(2) as usize, PAGE_SIZE, RW, noreplace, 0, 0];
+        assert_eq!(syscall(SYS_MMAP, overlapping), -EEXIST);
+        // MAP_FIXED 失败时原有的映射保持不变
+        let fixed = PRIVATE_ANONYMOUS | FIXED;
+        assert_eq!(syscall(SYS_MMAP, [page(1) as usize, usize::MAX, RW, fixed, 0, 0]), -EINVAL);
+        assert_eq!(unsafe { page(1).read_volatile() }, 0x1234);
+        assert_eq!(syscall(SYS_MMAP, [0, 0, RW, PRIVATE_ANONYMOUS, 0, 0]), -EINVAL);
+        assert_eq!(syscall(SYS_MMAP, [0, PAGE_SIZE, RW, 0x21, 0, 0]), -EINVAL);
+        assert_eq!(syscall(SYS_MUNMAP, [page(1) as usize + 1, PAGE_SIZE, 0, 0, 0, 0]), -EINVAL);
+        assert_eq!(syscall(SYS_MPROTECT, [page(0) as usize, PAGE_SIZE, R, 0, 0, 0]), -ENOMEM);
+        assert_eq!(syscall(0, [0; 6]), -ENOSYS);
+    });
+    println!("mmap test passed!");
+}
+
+// 写时复制测试函数
+fn test_cow() {
+    use alloc::sync::Arc;
+    use memory::address::{VirtualAddress, VirtualPageNumber};
+    use memory::config::PAGE_SIZE;
+    use memory::mapping::{self, Flags, MemorySet};
+    use spin::Mutex;
+    with_user_space(|parent| {
+        let rw = Flags::READABLE | Flags::WRITABLE;
+        let start = parent.lock().mmap(VirtualAddress(0), 2 * PAGE_SIZE, rw, false).unwrap();
+        let page = |i: usize| (start.0 + i * PAGE_SIZE) as *mut usize;
+        let frame_of = |memory_set: &Arc<Mutex<MemorySet>>, i: usize| {
+            memory_set.lock().mapping.translate(VirtualAddress(page(i) as usize)).unwrap()
+        };
+        unsafe {
+            page(0).write_volatile(1);
+            page(1).write_volatile(2);
+        }
+        // 复制地址空间后，两页都被共享
+        let child = Arc::new(Mutex::new(parent.lock().fork().unwrap()));
+        assert_eq!(frame_of(&child, 0), frame_of(parent, 0));
+        let vpn = VirtualPageNumber::floor(VirtualAddress(page(0) as usize));
+        assert!(!parent.lock().mapping.lookup(vpn).unwrap().flags().contains(Flags::WRITABLE));
+        // 父进程写入第 0 页，得到自己的副本
+        let shared = frame_of(parent, 0);
+        unsafe { page(0).write_volatile(10) };
+        assert_ne!(frame_of(parent, 0), shared);
+        // 子进程看到的仍然是复制前的数据；写入第 1 页时也得到副本
+        mapping::activate(child.clone());
+        assert_eq!(unsafe { page(0).read_volatile() }, 1);
+        unsafe { page(1).write_volatile(20) };
+        assert_eq!(unsafe { page(1).read_volatile() }, 20);
+        // 第 0 页只剩子进程持有，写入时不再复制
+        unsafe { page(0).write_volatile(30) };
+        assert_eq!(frame_of(&child, 0), shared);
+        // 第 1 页只剩父进程持有，同样直接恢复写权限
+        mapping::activate(parent.clone());
+        let original = frame_of(parent, 1);
+        assert_eq!(unsafe { page(1).read_volatile() }, 2);
+        unsafe { page(1).write_volatile(40) };
+        assert_eq!(frame_of(parent, 1), original);
+    });
+    println!("Copy-on-write test passed!");
+}
+
+// 页面置换测试函数
+fn test_swap() {
+    use memory::address::VirtualAddress;
+    use memory::config::PAGE_SIZE;
+    use memory::mapping::{self, Flags};
+    use memory::swap;
+    const PAGES: usize = 4;
+    with_user_space(|memory_set| {
+        let rw = Flags::READABLE | Flags::WRITABLE;
+        let length = PAGES * PAGE_SIZE;
+        let start = memory_set.lock().mmap(VirtualAddress(0), length, rw, false).unwrap();
+        let page = |i: usize| (start.0 + i * PAGE_SIZE) as *mut u64;
+        // 每一页的内容：第 0 页几乎全是零，其余页是不易压缩的伪随机数
+        let value = |i: usize, j: usize| match i {
+            0 => (j == 7) as u64,
+            _ => ((i * 512 + j) as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15),
+        };
+        unsafe {
+            for i in 0..PAGES {
+                for j in 0..PAGE_SIZE / 8 {
+                    page(i).add(j).write_volatile(value(i, j));
+                }
+            }
+        }
+        // 所有页都刚被访问过，第一轮只清除访问位，第二轮才换出
+        let before = swap::stats();
+        let major_faults = mapping::stats().major;
+        assert_eq!(memory_set.lock().swap_out(PAGES), PAGES);
+        assert!(memory_set.lock().mapping.translate(start).is_none());
+        assert_eq!(swap::stats().pages_out, before.pages_out + PAGES);
+        assert_eq!(swap::stats().used_slots, before.used_slots + PAGES);
+        // 再次访问时从交换区换入
+        for i in 0..PAGES {
+            for j in 0..PAGE_SIZE / 8 {
+                assert_eq!(unsafe { page(i).add(j).read_volatile() }, value(i, j));
+            }
+        }
+        assert_eq!(swap::stats().pages_in, before.pages_in + PAGES);
+        assert_eq!(mapping::stats().major, major_faults + PAGES);
+        // 换入后没有被写过的页，再次换出时不需要重新写入
+        assert_eq!(memory_set.lock().swap_out(PAGES), PAGES);
+        assert_eq!(swap::stats().pages_out, before.pages_out + PAGES);
+        // 换入后写过的页，修改权限之后再换出，交换区中的副本也要更新
+        let address = |i: usize| VirtualAddress(page(i) as usize);
+        let protect = |i: usize, flags: Flags| memory_set.lock().mprotect(address(i), PAGE_SIZE, flags);
+        unsafe { page(1).write_volatile(!value(1, 0)) };
+        protect(1, Flags::READABLE).unwrap();
+        assert_eq!(memory_set.lock().swap_out(1), 1);
+        assert_eq!(swap::stats().pages_out, before.pages_out + PAGES + 1);
+        assert_eq!(unsafe { page(1).read_volatile() }, !value(1, 0));
+        // 暂时取消所有权限时脏位随映射一起丢失，过期的副本被丢弃，换出时重新写入
+        unsafe { page(2).write_volatile(!value(2, 0)) };
+        protect(2, Flags::empty()).unwrap();
+        protect(2, rw).unwrap();
+        assert_eq!(memory_set.lock().swap_out(2), 2);
+        assert_eq!(swap::stats().pages_out, before.pages_out + PAGES + 2);
+        assert_eq!(unsafe { page(2).read_volatile() }, !value(2, 0));
+        // 解除映射后释放交换区中的槽位
+        memory_set.lock().munmap(start, length).unwrap();
+        assert_eq!(swap::stats().used_slots, before.used_slots);
+    });
+    println!("Swap test passed!");
+}
+
+// 用户态上下文测试函数
+// 真正进入用户态（`interrupt::enter_user`）之后不会再回到这里，这里只检查新建的上下文
+fn test_user_context() {
+    use riscv::register::sstatus::SPP;
+    let context = interrupt::Context::new_user(0x1000, 0x8000);
+    assert_eq!(core::mem::size_of::<interrupt::Context>(), 36 * 8);
+    assert!(context.is_user());
+    assert_eq!(context.spp(), SPP::User);
+    // 进入用户态后打开中断，sret 之前保持关闭
+    assert!(context.spie() && !context.sie());
+    assert_eq!((context.sepc, context.x[2]), (0x1000, 0x8000));
+    println!("User context test passed!");
+}
+
+// 陷入处理函数注册表测试函数
+fn test_trap_registry() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use interrupt::{Context, TrapResult, TrapSource};
+    use riscv::register::scause::{Exception, Scause};
+    static OBSERVED: AtomicUsize = AtomicUsize::new(0);
+    static ILLEGAL: AtomicUsize = AtomicUsize::new(0);
+    // 排在默认处理函数之前，只记录不处理
+    fn observe(_: &mut Context, _: Scause, _: usize) -> TrapResult {
+        OBSERVED.fetch_add(1, Ordering::Relaxed);
+        TrapResult::Pass
+    }
+    // 兜底处理所有异常：跳过非法指令
+    fn skip_illegal(context: &mut Context, scause: Scause, _: usize) -> TrapResult {
+        if scause.code() != 2 {
+            return TrapResult::Pass;
+        }
+        ILLEGAL.fetch_add(1, Ordering::Relaxed);
+        context.sepc += 4;
+        TrapResult::Handled
+    }
+    interrupt::register(Exception::Breakpoint, 0, "observe", observe).unwrap();
+    assert!(interrupt::register(Exception::Breakpoint, 0, "observe", observe).is_err());
+    interrupt::register(TrapSource::AnyException, 0, "skip illegal", skip_illegal).unwrap();
+    unsafe {
+        core::arch::asm!("ebreak");
+        // S 态不能访问 mstatus，触发非法指令异常，没有专门的处理函数，交给兜底的处理函数
+        core::arch::asm!("csrr t0, mstatus", out("t0") _);
+    }
+    assert_eq!(OBSERVED.load(Ordering::Relaxed), 1);
+    assert_eq!(ILLEGAL.load(Ordering::Relaxed), 1);
+    interrupt::unregister(Exception::Breakpoint, "observe").unwrap();
+    interrupt::unregister(TrapSource::AnyException, "skip illegal").unwrap();
+    unsafe { core::arch::asm!("ebreak") };
+    assert_eq!(OBSERVED.load(Ordering::Relaxed), 1);
+    println!("Trap registry test passed!");
+}
+
+// PLIC 测试函数
+fn test_plic() {
+    use alloc::sync::Arc;
+    use interrupt::plic;
+    use memory::mapping::{self, MemorySet};
+    use spin::Mutex;
+    fn uart(_: u32) {}
+    // 串口的中断号取自设备树，QEMU virt 上为 10
+    let irq = fdt::get()
+        .and_then(|fdt| fdt.find_compatible(&["ns16550a"]).next())
+        .and_then(|node| node.interrupts().next())
+        .unwrap_or(10);
+    plic::register_irq(irq, "uart", uart).unwrap();
+    assert!(plic::register_irq(irq, "uart", uart).is_err());
+    assert!(plic::register_irq(0, "reserved", uart).is_err());
+    assert_eq!(plic::irq_state(irq), Some((plic::IRQ_PRIORITY_DEFAULT, true)));
+    plic::unregister_irq(irq, "uart").unwrap();
+    assert_eq!(plic::irq_state(irq), Some((0, false)));
+    assert!(plic::unregister_irq(irq, "uart").is_err());
+    // 新建的地址空间中同样可以访问 PLIC 的寄存器
+    let memory_set = Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+    mapping::activate(memory_set);
+    assert_eq!(plic::irq_state(irq), Some((0, false)));
+    mapping::activate(mapping::KERNEL_MEMORY_SET.clone());
+    println!("PLIC test passed!");
+}
+
+// 关闭中断的临界区测试函数
+fn test_critical_section() {
+    use interrupt::{InterruptGuard, IrqMutex};
+    use riscv::register::sstatus;
+    assert!(sstatus::read().sie());
+    // 嵌套的临界区，只有离开最外层时才打开中断
+    let outer = InterruptGuard::new();
+    let inner = InterruptGuard::new();
+    assert!(!sstatus::read().sie());
+    drop(inner);
+    assert!(!sstatus::read().sie());
+    drop(outer);
+    assert!(sstatus::read().sie());
+    interrupt::push_off();
+    interrupt::without_interrupts(|| assert_eq!(interrupt::depth(), 2));
+    interrupt::pop_off();
+    assert!(sstatus::read().sie() && interrupt::depth() == 0);
+    // 持有 IrqMutex 期间中断关闭，同时持有两把锁也一样
+    static COUNTER: IrqMutex<usize> = IrqMutex::new(0);
+    static OTHER: IrqMutex<usize> = IrqMutex::new(0);
+    {
+        let mut counter = COUNTER.lock();
+        let mut other = OTHER.lock();
+        assert!(!sstatus::read().sie());
+        assert!(COUNTER.try_lock().is_none());
+        *counter += 1;
+        *other += 1;
+    }
+    assert!(sstatus::read().sie());
+    assert_eq!(*COUNTER.lock(), 1);
+    // 时钟中断照常到来
+    let ticks = interrupt::ticks();
+    while interrupt::ticks() == ticks {
+        core::hint::spin_loop();
+    }
+    println!("Critical section test passed!");
+}
+
+// 指令解码和非对齐访存模拟测试函数
+fn test_instruction() {
+    use interrupt::Context;
+    use memory::address::VirtualAddress;
+    use memory::config::PAGE_SIZE;
+    use memory::mapping::Flags;
+    use interrupt::instruction::{self, Operation};
+    // c.ebreak 和 ebreak
+    assert_eq!(instruction::decode(0x9002).length, 2);
+    assert_eq!(instruction::decode(0x0010_0073).length, 4);
+    // ld t0, 0(x0)
+    let load = |rd, base, offset, width| Operation::Load { rd, base, offset, width, signed: true };
+    assert_eq!(instruction::decode(0x0000_3283).operation, load(5, 0, 0, 8));
+    // lw a0, -4(sp) / c.lw a0, 4(a1)
+    assert_eq!(instruction::decode(0xffc1_2503).operation, load(10, 2, -4, 4));
+    assert_eq!(instruction::decode(0x41c8).operation, load(10, 11, 4, 4));
+    // sd a1, 8(a0) / c.sdsp ra, 8(sp)
+    let store = |rs2, base, offset, width| Operation::Store { rs2, base, offset, width };
+    assert_eq!(instruction::decode(0x00b5_3423).operation, store(11, 10, 8, 8));
+    assert_eq!(instruction::decode(0xe406).operation, store(1, 2, 8, 8));
+    // 在用户页上模拟 `lw a0, 0(a1)` 和 `c.sw a0, 0(a1)`，访问的地址不对齐
+    static CODE: [u16; 5] = [0xa503, 0x0005, 0xc188, 0x0073, 0x0000];
+    with_user_space(|memory_set| {
+        let rw = Flags::READABLE | Flags::WRITABLE;
+        let start = memory_set.lock().mmap(VirtualAddress(0), PAGE_SIZE, rw, false).unwrap();
+        let buffer = start.0 as *mut u8;
+        let address = start.0 + 1;
+        unsafe {
+            for (i, byte) in [0x80, 0x00, 0x00, 0xff].into_iter().enumerate() {
+                buffer.add(1 + i).write_volatile(byte);
+            }
+        }
+        let mut context = Context::new_user(CODE.as_ptr() as usize, 0);
+        instruction::emulate_misaligned(&mut context, address).unwrap();
+        assert_eq!(context.x[10], 0xffff_ffff_ff00_0080);
+        assert_eq!(context.sepc, CODE.as_ptr() as usize + 4);
+        context.x[10] = 0x1234_5678;
+        instruction::emulate_misaligned(&mut context, address).unwrap();
+        let bytes: [u8; 4] = core::array::from_fn(|i| unsafe { buffer.add(1 + i).read_volatile() });
+        assert_eq!(bytes, [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(context.sepc, CODE.as_ptr() as usize + 6);
+        // ecall 不是访存指令，无法模拟
+        assert!(instruction::emulate_misaligned(&mut context, address).is_err());
+        // 用户态的上下文不能借助模拟访问内核的内存，也不能越过用户页的末尾，失败时上下文保持不变
+        let mut kernel_buffer = [0u8; 8];
+        let kernel_address = kernel_buffer.as_mut_ptr() as usize + 1;
+        context.sepc = CODE.as_ptr() as usize + 4;
+        assert!(instruction::emulate_misaligned(&mut context, kernel_address).is_err());
+        assert!(instruction::emulate_misaligned(&mut context, start.0 + PAGE_SIZE - 2).is_err());
+        assert_eq!(kernel_buffer, [0; 8]);
+        assert_eq!(context.sepc, CODE.as_ptr() as usize + 4);
+    });
+    println!("Instruction test passed!");
+}
+
+// 内存布局测试函数
+fn test_layout() {
+    use memory::frame::FRAME_ALLOCATOR;
+    use memory::layout;
+    let before = layout::layout();
+    // 各个区域都位于内核镜像之内，并且互不重叠（启动堆位于 .bss 之中）
+    for region in &before.regions {
+        assert!(region.size() > 0, "{} is empty", region.name);
+        assert!(before.kernel.start <= region.range.start && region.range.end <= before.kernel.end);
+    }
+    for pair in before.regions[..6].windows(2) {
+        assert!(pair[0].range.end <= pair[1].range.start);
+    }
+    // 空闲帧数随分配和释放变化
+    let frame = FRAME_ALLOCATOR.lock().alloc().unwrap();
+    assert_eq!(layout::layout().free_frames, before.free_frames - 1);
+    drop(frame);
+    assert_eq!(layout::layout().free_frames, before.free_frames);
+    println!("Layout test passed!");
+}
+
+// 打印设备树中的硬件信息
+fn print_device_tree(hart_id: usize) {
+    let Some(fdt) = fdt::get() else {
+        println!("No device tree, running on hart {}", hart_id);
+        return;
+    };
+    println!("Booted on hart {}, device tree at {:x?}", hart_id, fdt.physical_range());
+    for region in fdt.memory_regions() {
+        println!("memory: {:x?}", region);
+    }
+    println!("bootargs: {:?}", fdt.bootargs());
+    println!("timebase frequency: {:?}", fdt.timebase_frequency());
+    for cpu in fdt.cpus() {
+        println!("cpu: hart {} isa {:?} enabled {}", cpu.hart_id, cpu.isa, cpu.enabled);
+    }
+    for device in fdt.devices() {
+        println!(
+            "device {}: compatible {:?}, reg {:x?}, interrupts {:?}",
+            device.name,
+            device.compatible().next(),
+            device.reg().next(),
+            device.interrupts().next()
+        );
+    }
+}
+
 // --- 内核入口函数 ---
 // 5. #[unsafe(no_mangle)]：告诉编译器不要混淆函数名，确保汇编能通过 "rust_main" 找到它。
 // 6. extern "C"：使用 C 语言的函数调用约定，确保汇编和 Rust 能正常传递参数。
+// 两个参数由 OpenSBI 通过 a0、a1 传入，`entry.asm` 原样保留：
+// - hart_id: 当前 hart 的编号
+// - dtb: 设备树的物理地址
 #[unsafe(no_mangle)]
-pub extern "C" fn rust_main() -> ! {
+pub extern "C" fn rust_main(hart_id: usize, dtb: usize) -> ! {
+    // 设备树的解析不需要堆，最先进行，其余模块的初始化都可以用到其中的信息
+    fdt::init(memory::address::PhysicalAddress(dtb));
     // 初始化各种模块
     interrupt::init();
     memory::init();
+    // PLIC 的寄存器要映射到内核地址空间中，在内存模块之后初始化
+    interrupt::plic::init();
     // 调用上面定义的函数，在屏幕上打印 "OK"
     console_putchar(b'O');
     console_putchar(b'K');
@@ -108,9 +908,32 @@ pub extern "C" fn rust_main() -> ! {
     // 内核加载地址：通常 OpenSBI 把内核加载在 0x80200000。
     // 内核结束地址：0x809D0AD8。
     // 内核（包括代码、数据、栈空间）一共占用了大约 7.8 MB （KERNEL_HEAP_SIZE 为 8 MB）的内存空间（即 0x809D0AD8 减去 0x80200000）。
-    use alloc::format;
-    println!("| Kernel boundary: {:<20} |", format!("{:?}", *memory::config::KERNEL_END_ADDRESS));
+    memory::layout::print();
+    print_device_tree(hart_id);
+    test_layout();
     test_heap();
+    test_heap_stats();
+    test_slab();
+    test_heap_growth();
+    test_fallible_alloc();
+    #[cfg(feature = "heap_sanitizer")]
+    test_heap_sanitizer();
+    test_frame();
+    test_dma();
+    test_memory_set();
+    test_segment_rollback();
+    test_demand_paging();
+    test_huge_pages();
+    test_asid();
+    test_kernel_stack();
+    test_mmap();
+    test_cow();
+    test_swap();
+    test_user_context();
+    test_trap_registry();
+    test_plic();
+    test_critical_section();
+    test_instruction();
     unsafe {
         core::arch::asm!("ebreak");
     };
@@ -123,5 +946,10 @@ pub extern "C" fn rust_main() -> ! {
     // panic!("end of rust_main");
     loop {
         // CPU 在这里空转，等待时钟中断强行打断它。
+        // 时钟中断只负责计时，时间到了之后在这里打印堆的报告并关机
+        if interrupt::time_is_up() {
+            memory::heap::leak_report();
+            sbi::shutdown();
+        }
     }
 }
@@ -1,19 +1,38 @@
+// 地址与页号
+// 内存管理中会同时出现四种“数字”：物理地址、虚拟地址、物理页号、虚拟页号。
+// 它们本质上都是 `usize`，但含义完全不同，混用会造成难以排查的错误。
+// 这里为每一种都定义单独的类型，并只提供有意义的转换和运算。
+
+use super::config::{KERNEL_MAP_OFFSET, PAGE_SIZE};
 use core::fmt;
+use core::iter::Step;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
 
 // 物理地址 (Physical Address) 的包装类型
 // 使用 Newtype 模式封装 `usize`：
 // 1. 类型安全：防止物理地址、虚拟地址和普通整数之间发生非预期的隐式转换。
 // 2. 语义化：在函数参数中使用 `PhysicalAddress` 比直接用 `usize` 更能清晰表达意图。
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct PhysicalAddress(pub usize);
 
-// 为 PhysicalAddress 实现 `Display` trait，方便在内核调试时直接通过 `println!` 输出
-impl fmt::Display for PhysicalAddress {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // 以 16 进制格式打印地址
-        write!(f, "PhysicalAddress(0x{:x})", self.0)
-    }
-}
+// 虚拟地址 (Virtual Address)
+// 开启分页后，CPU 访问的都是虚拟地址，需要经过页表翻译才能得到物理地址。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct VirtualAddress(pub usize);
+
+// 物理页号 (Physical Page Number)
+// 物理地址除以 PAGE_SIZE，即第几个物理页（帧）。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PhysicalPageNumber(pub usize);
+
+// 虚拟页号 (Virtual Page Number)
+// 虚拟地址除以 PAGE_SIZE，即第几个虚拟页。
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct VirtualPageNumber(pub usize);
 
 impl PhysicalAddress {
     // 将物理地址转换为原始指针
@@ -30,4 +49,232 @@ impl PhysicalAddress {
     pub fn as_usize(&self) -> usize {
         self.0
     }
+
+    // 通过内核的线性映射，把物理地址当作 `T` 类型的引用来访问
+    // 开启分页后物理地址不能直接当作指针使用，需要先加上 `KERNEL_MAP_OFFSET`
+    //
+    // # Safety
+    // 同 [`VirtualAddress::deref`]：该物理地址必须位于线性映射覆盖的内存中，按 `T` 对齐并存放着有效的 `T`，
+    // 并且返回的引用存在期间没有其他引用访问同一块内存。
+    #[allow(dead_code)]
+    pub unsafe fn deref_kernel<T>(self) -> &'static mut T {
+        unsafe { VirtualAddress::from(self).deref() }
+    }
+}
+
+impl VirtualAddress {
+    // 将虚拟地址转换为原始指针
+    #[allow(dead_code)]
+    pub fn as_ptr(&self) -> *const u8 {
+        self.0 as *const u8
+    }
+
+    // 获取内部的数值
+    #[allow(dead_code)]
+    pub fn as_usize(&self) -> usize {
+        self.0
+    }
+
+    // 把虚拟地址当作 `T` 类型的引用来访问
+    //
+    // # Safety
+    // 该地址必须在当前页表中已经映射且可以读写，按 `T` 对齐并存放着有效的 `T`；
+    // 返回的引用存在期间，不能有其他引用访问同一块内存。
+    #[allow(dead_code)]
+    pub unsafe fn deref<T>(self) -> &'static mut T {
+        unsafe { &mut *(self.0 as *mut T) }
+    }
+}
+
+impl VirtualPageNumber {
+    // 得到 Sv39 三级页表中每一级的页号（VPN[2], VPN[1], VPN[0]）
+    // Sv39 的 27 位虚拟页号被切成三段，每段 9 位，分别用来索引三级页表。
+    #[allow(dead_code)]
+    pub fn levels(self) -> [usize; 3] {
+        [
+            (self.0 >> 18) & 0x1ff,
+            (self.0 >> 9) & 0x1ff,
+            self.0 & 0x1ff,
+        ]
+    }
+}
+
+impl PhysicalPageNumber {
+    // 通过内核的线性映射访问整个物理页
+    //
+    // # Safety
+    // 同 [`PhysicalAddress::deref_kernel`]：该页必须由线性映射覆盖，并且返回的引用存在期间没有其他引用访问这一页。
+    #[allow(dead_code)]
+    pub unsafe fn deref_kernel(self) -> &'static mut [u8; PAGE_SIZE] {
+        unsafe { PhysicalAddress::from(self).deref_kernel() }
+    }
+}
+
+// 内核的线性映射：虚拟地址 = 物理地址 + KERNEL_MAP_OFFSET
+// 以下转换只对内核线性映射区域内的地址有意义，例如内核镜像和帧分配器管理的物理内存；
+// 用户程序的地址需要查询页表才能得到对应的物理地址。
+impl From<PhysicalAddress> for VirtualAddress {
+    fn from(pa: PhysicalAddress) -> Self {
+        Self(pa.0 + KERNEL_MAP_OFFSET)
+    }
+}
+
+// 反过来的转换只对线性映射区域内的地址成立，其他地址返回错误
+impl TryFrom<VirtualAddress> for PhysicalAddress {
+    type Error = &'static str;
+    fn try_from(va: VirtualAddress) -> Result<Self, Self::Error> {
+        va.0
+            .checked_sub(KERNEL_MAP_OFFSET)
+            .map(Self)
+            .ok_or("not a kernel linear-mapped address")
+    }
+}
+
+impl From<PhysicalPageNumber> for VirtualPageNumber {
+    fn from(ppn: PhysicalPageNumber) -> Self {
+        Self(ppn.0 + KERNEL_MAP_OFFSET / PAGE_SIZE)
+    }
+}
+
+impl TryFrom<VirtualPageNumber> for PhysicalPageNumber {
+    type Error = &'static str;
+    fn try_from(vpn: VirtualPageNumber) -> Result<Self, Self::Error> {
+        vpn.0
+            .checked_sub(KERNEL_MAP_OFFSET / PAGE_SIZE)
+            .map(Self)
+            .ok_or("not a kernel linear-mapped page")
+    }
+}
+
+// 为地址类型实现页内偏移和与页号之间的转换
+macro_rules! implement_address_to_page_number {
+    // 地址类型，页号类型
+    ($address_type: ty, $page_number_type: ty) => {
+        impl $address_type {
+            // 页内偏移
+            #[allow(dead_code)]
+            pub fn page_offset(&self) -> usize {
+                self.0 % PAGE_SIZE
+            }
+
+            // 是否按页对齐
+            #[allow(dead_code)]
+            pub fn is_aligned(&self) -> bool {
+                self.page_offset() == 0
+            }
+        }
+
+        impl $page_number_type {
+            // 将地址转换为页号，向下取整
+            #[allow(dead_code)]
+            pub const fn floor(address: $address_type) -> Self {
+                Self(address.0 / PAGE_SIZE)
+            }
+
+            // 将地址转换为页号，向上取整
+            #[allow(dead_code)]
+            pub const fn ceil(address: $address_type) -> Self {
+                Self(address.0.div_ceil(PAGE_SIZE))
+            }
+
+            // 页号对应页的起始地址
+            #[allow(dead_code)]
+            pub fn start_address(&self) -> $address_type {
+                <$address_type>::from(*self)
+            }
+        }
+
+        // 从页号转换为地址：得到页的起始地址
+        impl From<$page_number_type> for $address_type {
+            fn from(page_number: $page_number_type) -> Self {
+                Self(page_number.0 * PAGE_SIZE)
+            }
+        }
+
+        // 从地址转换为页号：地址没有按页对齐时返回错误
+        // 需要取整时请使用 `floor` / `ceil`
+        impl TryFrom<$address_type> for $page_number_type {
+            type Error = &'static str;
+            fn try_from(address: $address_type) -> Result<Self, Self::Error> {
+                if !address.is_aligned() {
+                    return Err("address is not page aligned");
+                }
+                Ok(Self(address.0 / PAGE_SIZE))
+            }
+        }
+    };
+}
+implement_address_to_page_number! {PhysicalAddress, PhysicalPageNumber}
+implement_address_to_page_number! {VirtualAddress, VirtualPageNumber}
+
+// 为四种类型实现与 `usize` 之间的运算和转换
+macro_rules! implement_usize_operations {
+    ($type_name: ty) => {
+        // `+`
+        impl Add<usize> for $type_name {
+            type Output = Self;
+            fn add(self, other: usize) -> Self::Output {
+                Self(self.0 + other)
+            }
+        }
+        // `+=`
+        impl AddAssign<usize> for $type_name {
+            fn add_assign(&mut self, rhs: usize) {
+                self.0 += rhs;
+            }
+        }
+        // `-`
+        impl Sub<usize> for $type_name {
+            type Output = Self;
+            fn sub(self, other: usize) -> Self::Output {
+                Self(self.0 - other)
+            }
+        }
+        // `-`，两个同类型相减得到它们之间的距离
+        impl Sub<$type_name> for $type_name {
+            type Output = usize;
+            fn sub(self, other: $type_name) -> Self::Output {
+                self.0 - other.0
+            }
+        }
+        // `-=`
+        impl SubAssign<usize> for $type_name {
+            fn sub_assign(&mut self, rhs: usize) {
+                self.0 -= rhs;
+            }
+        }
+        // 和 usize 相互转换
+        impl From<usize> for $type_name {
+            fn from(value: usize) -> Self {
+                Self(value)
+            }
+        }
+        impl From<$type_name> for usize {
+            fn from(value: $type_name) -> Self {
+                value.0
+            }
+        }
+        // 支持 `start..end` 形式的区间迭代，例如逐页遍历 `VirtualPageNumber` 区间
+        impl Step for $type_name {
+            fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
+                usize::steps_between(&start.0, &end.0)
+            }
+            fn forward_checked(start: Self, count: usize) -> Option<Self> {
+                start.0.checked_add(count).map(Self)
+            }
+            fn backward_checked(start: Self, count: usize) -> Option<Self> {
+                start.0.checked_sub(count).map(Self)
+            }
+        }
+        // 以 16 进制格式打印，方便内核调试时直接通过 `println!` 输出
+        impl fmt::Display for $type_name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}(0x{:x})", stringify!($type_name), self.0)
+            }
+        }
+    };
 }
+implement_usize_operations! {PhysicalAddress}
+implement_usize_operations! {VirtualAddress}
+implement_usize_operations! {PhysicalPageNumber}
+implement_usize_operations! {VirtualPageNumber}
@@ -1,14 +1,81 @@
-// 操作系统动态分配内存所用的堆大小（8M）
+// 启动堆的大小（1M）
+// 帧分配器就绪之前只能使用这块编译进 .bss 段的静态空间
+pub const BOOT_HEAP_SIZE: usize = 0x10_0000;
+
+// 操作系统动态分配内存所用的堆的默认初始大小（8M）
 // 0x80_0000 换算成十进制就是 8,388,608 字节，即 8MB
-pub const KERNEL_HEAP_SIZE: usize = 0x80_0000;
+// 可以通过 [`super::heap::HeapConfig`] 在运行时修改
+pub const KERNEL_HEAP_INITIAL_SIZE: usize = 0x80_0000;
+
+// 堆的默认上限（64M），超过后分配失败
+pub const KERNEL_HEAP_MAX_SIZE: usize = 0x400_0000;
+
+// 堆空间不足时，每次至少扩展的大小（1M）
+pub const KERNEL_HEAP_GROW_STEP: usize = 0x10_0000;
+
+// 页 / 帧大小，必须是 2^n
+// RISC-V Sv39 下一页为 4KB
+pub const PAGE_SIZE: usize = 4096;
+
+// 没有设备树时使用的内存区域起始地址，即 QEMU virt 机器上 DRAM 的起始位置
+pub const DEFAULT_MEMORY_START_ADDRESS: PhysicalAddress = PhysicalAddress(0x8000_0000);
+
+// 没有设备树时使用的内存区域结束地址
+// QEMU 默认分配 128MB 内存，因此结束于 0x88000000
+pub const DEFAULT_MEMORY_END_ADDRESS: PhysicalAddress = PhysicalAddress(0x8800_0000);
+
+// 内核使用线性映射的偏移量
+// 内核被链接在 0xffff_ffff_8020_0000，实际位于物理地址 0x8020_0000，
+// 两者之差即为此偏移量：虚拟地址 = 物理地址 + KERNEL_MAP_OFFSET。
+// 这样地址空间的低半部分就可以全部留给用户程序。
+pub const KERNEL_MAP_OFFSET: usize = 0xffff_ffff_0000_0000;
+
+// 内核栈的大小（64K），必须是 2^n
+// 每个内核栈位于按 2 * KERNEL_STACK_SIZE 对齐的槽位的上半部分，下半部分作为保护区域，
+// `interrupt.asm` 通过检查栈指针的第 16 位来发现栈溢出，修改时需要一并修改
+pub const KERNEL_STACK_SIZE: usize = 0x1_0000;
+
+// 动态分配的内核栈所在区域的起始地址，位于线性映射区域之外
+pub const KERNEL_STACK_AREA_START: VirtualAddress = VirtualAddress(0xffff_ffc0_0000_0000);
+
+// 最多同时存在的动态分配的内核栈个数
+pub const MAX_KERNEL_STACKS: usize = 64;
+
+// 用户地址空间的结束地址，即 Sv39 地址空间的低半部分（256G）
+pub const USER_SPACE_END: VirtualAddress = VirtualAddress(0x40_0000_0000);
+
+// 不指定地址的 mmap 从这里开始寻找空闲区域
+pub const USER_MMAP_BASE: VirtualAddress = VirtualAddress(0x10_0000_0000);
 
 use lazy_static::lazy_static;
-use super::address::PhysicalAddress;
+use super::address::{PhysicalAddress, VirtualAddress};
+use core::ops::Range;
 
 lazy_static! {
-    // 内核代码结束的地址，即可以用来分配的内存起始地址
+    // 内核代码结束的地址（虚拟地址），其对应的物理地址之后即可以用来分配的内存
     // 这里修复了“函数直接强转 usize”的警告
-    pub static ref KERNEL_END_ADDRESS: PhysicalAddress = PhysicalAddress(kernel_end as *const () as usize);
+    pub static ref KERNEL_END_ADDRESS: VirtualAddress = VirtualAddress(kernel_end as *const () as usize);
+
+    // 内核所在内存区域的起始地址，取自设备树
+    pub static ref MEMORY_START_ADDRESS: PhysicalAddress =
+        kernel_memory_region().map_or(DEFAULT_MEMORY_START_ADDRESS, |region| region.start);
+
+    // 可以访问的内存区域结束地址
+    // 取设备树中包含内核的那段内存的结束地址；
+    // 线性映射最多只能覆盖 4GB 的物理地址（最后一页除外，避免地址溢出），超出的部分不使用
+    pub static ref MEMORY_END_ADDRESS: PhysicalAddress = kernel_memory_region()
+        .map_or(DEFAULT_MEMORY_END_ADDRESS, |region| region.end)
+        .min(PhysicalAddress(!KERNEL_MAP_OFFSET - PAGE_SIZE + 1));
+}
+
+// 设备树中包含内核的那段内存
+fn kernel_memory_region() -> Option<Range<PhysicalAddress>> {
+    // 内核链接在线性映射区域中，转换不会失败
+    let kernel_end = PhysicalAddress::try_from(*KERNEL_END_ADDRESS).unwrap();
+    crate::fdt::get().and_then(|fdt| {
+        fdt.memory_regions()
+            .find(|region| region.start <= kernel_end && kernel_end < region.end)
+    })
 }
 
 unsafe extern "C" {
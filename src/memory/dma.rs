@@ -0,0 +1,99 @@
+// 供 DMA 使用的物理连续内存
+// Virtio 等设备直接按物理地址读写内存，缓冲区必须物理连续、按页对齐，并且内核要知道它的物理地址。
+// [`DmaBuffer`] 从帧分配器申请连续的物理页，同时给出内核访问它的虚拟地址（线性映射）和交给设备的物理地址，
+// drop 时把这些页还给帧分配器。
+//
+// QEMU 的 virt 平台上设备访问内存是缓存一致的，因此这里不需要额外刷新缓存。
+
+use super::{MemoryResult, address::*, config::PAGE_SIZE, frame::FRAME_ALLOCATOR, heap};
+use core::ops::{Deref, DerefMut, Range};
+
+// 物理连续、按页对齐的 DMA 缓冲区，内容初始为 0
+#[derive(Debug)]
+pub struct DmaBuffer {
+    pages: Range<PhysicalPageNumber>,
+}
+
+#[allow(dead_code)]
+impl DmaBuffer {
+    // 分配 `pages` 个连续的物理页
+    pub fn new(pages: usize) -> MemoryResult<Self> {
+        Self::with_alignment(pages, 1)
+    }
+
+    // 分配至少能放下 `size` 字节的连续物理页
+    pub fn with_size(size: usize) -> MemoryResult<Self> {
+        Self::new(size.div_ceil(PAGE_SIZE))
+    }
+
+    // 分配 `pages` 个连续的物理页，起始地址按 `align` 个页对齐（`align` 为 2 的幂）
+    // 有些设备要求队列等结构按更大的粒度对齐
+    pub fn with_alignment(pages: usize, align: usize) -> MemoryResult<Self> {
+        if pages == 0 {
+            return Err("DMA buffer must not be empty");
+        }
+        if !align.is_power_of_two() {
+            return Err("DMA alignment must be a power of two");
+        }
+        let range = FRAME_ALLOCATOR.lock().alloc_contiguous(pages, align);
+        let range = match range {
+            Ok(range) => range,
+            // 堆借走的连续物理页空闲时可以先还回来，再试一次
+            Err(_) if heap::shrink() > 0 => {
+                FRAME_ALLOCATOR.lock().alloc_contiguous(pages, align)?
+            }
+            Err(error) => return Err(error),
+        };
+        let mut buffer = Self { pages: range };
+        buffer.fill(0);
+        Ok(buffer)
+    }
+
+    // 交给设备的物理地址
+    pub fn physical_address(&self) -> PhysicalAddress {
+        PhysicalAddress::from(self.pages.start)
+    }
+
+    // 内核访问缓冲区使用的虚拟地址
+    pub fn virtual_address(&self) -> VirtualAddress {
+        VirtualAddress::from(self.physical_address())
+    }
+
+    // 缓冲区占用的物理页
+    pub fn pages(&self) -> Range<PhysicalPageNumber> {
+        self.pages.clone()
+    }
+
+    // 缓冲区的大小（字节）
+    pub fn size(&self) -> usize {
+        (self.pages.end - self.pages.start) * PAGE_SIZE
+    }
+
+    // 缓冲区中 `offset` 处的物理地址，例如把缓冲区的一部分交给设备
+    pub fn physical_address_at(&self, offset: usize) -> Option<PhysicalAddress> {
+        (offset < self.size()).then(|| PhysicalAddress(self.physical_address().0 + offset))
+    }
+}
+
+// 可以像 `[u8]` 一样直接读写缓冲区
+impl Deref for DmaBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &Self::Target {
+        unsafe { core::slice::from_raw_parts(self.virtual_address().0 as *const u8, self.size()) }
+    }
+}
+
+impl DerefMut for DmaBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { core::slice::from_raw_parts_mut(self.virtual_address().0 as *mut u8, self.size()) }
+    }
+}
+
+// 释放时把物理页还给帧分配器
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        FRAME_ALLOCATOR
+            .lock()
+            .dealloc_contiguous(self.pages.clone());
+    }
+}
@@ -0,0 +1,81 @@
+// 位图分配算法
+// 每个页对应一个比特位：1 表示已分配，0 表示空闲。
+// 分配时从上一次分配的位置开始向后寻找空闲位（next-fit），回收时清零对应位。
+// 位图天然知道每个页的状态，相邻的空闲页始终是连续的，不会像栈式算法那样被切碎。
+
+use super::Allocator;
+use alloc::{vec, vec::Vec};
+
+// 每个比特组的位数
+const BITS: usize = u64::BITS as usize;
+
+pub struct BitmapAllocator {
+    // 位图本体
+    bitmap: Vec<u64>,
+    // 管理的页数
+    capacity: usize,
+    // 下一次开始查找的比特组下标
+    cursor: usize,
+}
+
+impl Allocator for BitmapAllocator {
+    fn new(capacity: usize) -> Self {
+        let mut bitmap = vec![0u64; capacity.div_ceil(BITS)];
+        // 最后一组中超出 capacity 的位标记为已分配，防止被分出去
+        if !capacity.is_multiple_of(BITS) {
+            *bitmap.last_mut().unwrap() = !0u64 << (capacity % BITS);
+        }
+        Self {
+            bitmap,
+            capacity,
+            cursor: 0,
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let groups = self.bitmap.len();
+        for i in 0..groups {
+            let group = (self.cursor + i) % groups;
+            let bits = self.bitmap[group];
+            if bits != !0u64 {
+                // 找到该组中最低的空闲位
+                let bit = (!bits).trailing_zeros() as usize;
+                self.bitmap[group] |= 1 << bit;
+                self.cursor = group;
+                return Some(group * BITS + bit);
+            }
+        }
+        None
+    }
+
+    fn alloc_contiguous(&mut self, count: usize, align: usize, offset: usize) -> Option<usize> {
+        assert!(align.is_power_of_two());
+        // 依次尝试每一个满足对齐要求的起点
+        let mut aligned = (offset + align - 1) & !(align - 1);
+        while aligned - offset + count <= self.capacity {
+            let start = aligned - offset;
+            if (start..start + count).all(|index| !self.is_allocated(index)) {
+                for index in start..start + count {
+                    self.bitmap[index / BITS] |= 1 << (index % BITS);
+                }
+                return Some(start);
+            }
+            aligned += align;
+        }
+        None
+    }
+
+    fn dealloc(&mut self, index: usize) {
+        assert!(index < self.capacity, "dealloc index out of range");
+        let (group, bit) = (index / BITS, index % BITS);
+        assert!(self.bitmap[group] & (1 << bit) != 0, "dealloc a free frame");
+        self.bitmap[group] &= !(1 << bit);
+    }
+}
+
+impl BitmapAllocator {
+    // 下标为 `index` 的页是否已经分配
+    fn is_allocated(&self, index: usize) -> bool {
+        self.bitmap[index / BITS] & (1 << (index % BITS)) != 0
+    }
+}
@@ -0,0 +1,40 @@
+// 物理页分配算法
+// 算法只和“第几个页”打交道：对它来说，可分配的区域就是 [0, capacity) 的一串下标，
+// 下标和真实物理地址之间的换算交给 [`super::allocator::FrameAllocator`] 完成。
+
+// 同一时刻只会启用其中一种算法，另一种暂时忽略未使用警告
+#![allow(dead_code, unused_imports)]
+
+mod bitmap;
+mod stacked;
+
+pub use bitmap::BitmapAllocator;
+pub use stacked::StackedAllocator;
+
+// 分配器算法需要实现的接口
+pub trait Allocator {
+    // 创建一个管理 `capacity` 个页的分配器
+    fn new(capacity: usize) -> Self;
+    // 分配一个页，返回它的下标；没有空闲页时返回 `None`
+    fn alloc(&mut self) -> Option<usize>;
+    // 回收下标为 `index` 的页
+    fn dealloc(&mut self, index: usize);
+    // 分配 `count` 个连续的页，返回第一个页的下标
+    // 返回的下标满足 `(index + offset) % align == 0`（`align` 为 2 的幂），
+    // 这样调用者可以让真实的物理页号按 `align` 对齐。
+    // 这个函数可能在堆空间耗尽时被调用，因此实现中不能分配堆内存。
+    fn alloc_contiguous(&mut self, count: usize, align: usize, offset: usize) -> Option<usize>;
+    // 回收从 `start` 开始的 `count` 个连续的页
+    fn dealloc_contiguous(&mut self, start: usize, count: usize) {
+        for index in start..start + count {
+            self.dealloc(index);
+        }
+    }
+}
+
+// 实际使用的分配算法
+// 默认使用栈式分配；开启 `bitmap_frame_allocator` 功能后改用位图，便于比较两者的碎片情况
+#[cfg(not(feature = "bitmap_frame_allocator"))]
+pub type AllocatorImpl = StackedAllocator;
+#[cfg(feature = "bitmap_frame_allocator")]
+pub type AllocatorImpl = BitmapAllocator;
@@ -0,0 +1,96 @@
+// 栈式分配算法
+// 用一个栈记录所有空闲区间 [start, end)：
+// - 分配时从栈顶区间切下第一个页；
+// - 回收时直接把这个页作为长度为 1 的区间压回栈中。
+// 实现简单、分配和回收都是 O(1)，但回收的页不会与相邻区间合并，碎片会越来越多。
+
+use super::Allocator;
+use alloc::{vec, vec::Vec};
+
+pub struct StackedAllocator {
+    // 空闲区间列表，每一项为左闭右开的 (start, end)
+    list: Vec<(usize, usize)>,
+    // 管理的页数
+    capacity: usize,
+}
+
+impl StackedAllocator {
+    // 回收 [start, end) 之前检查：必须在管理范围内，并且没有与空闲区间重叠（重复释放）
+    // 检查需要遍历整个栈，只在 debug 构建中进行
+    fn debug_check_dealloc(&self, start: usize, end: usize) {
+        debug_assert!(end <= self.capacity, "dealloc index out of range");
+        debug_assert!(
+            !self.list.iter().any(|&(s, e)| start < e && s < end),
+            "dealloc a free frame"
+        );
+    }
+}
+
+impl Allocator for StackedAllocator {
+    fn new(capacity: usize) -> Self {
+        // 没有可用的页时栈为空，不能放入空区间，否则 `alloc` 会把它当成一个页分出去
+        let list = if capacity == 0 {
+            Vec::new()
+        } else {
+            vec![(0, capacity)]
+        };
+        Self { list, capacity }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        let (start, end) = self.list.pop()?;
+        // 区间里还剩下不止一个页，把剩余部分放回去
+        if end - start > 1 {
+            self.list.push((start + 1, end));
+        }
+        Some(start)
+    }
+
+    fn dealloc(&mut self, index: usize) {
+        self.debug_check_dealloc(index, index + 1);
+        self.list.push((index, index + 1));
+    }
+
+    fn alloc_contiguous(&mut self, count: usize, align: usize, offset: usize) -> Option<usize> {
+        assert!(align.is_power_of_two());
+        // 从栈顶开始，找到第一个放得下的区间，从它的末尾切下满足对齐要求的一段
+        for i in (0..self.list.len()).rev() {
+            let (start, end) = self.list[i];
+            if end - start < count {
+                continue;
+            }
+            // 区间内满足对齐要求的最高起点
+            let aligned = (end + offset - count) & !(align - 1);
+            if aligned < start + offset {
+                continue;
+            }
+            let index = aligned - offset;
+            // 切下后区间的前后两部分
+            let head = (start, index);
+            let tail = (index + count, end);
+            match (head.0 < head.1, tail.0 < tail.1) {
+                (false, false) => {
+                    self.list.remove(i);
+                }
+                (false, true) => self.list[i] = tail,
+                (true, false) => self.list[i] = head,
+                (true, true) => {
+                    // 需要多占用一项，只有在不用扩容（不分配堆内存）时才这样切分
+                    if self.list.len() == self.list.capacity() {
+                        continue;
+                    }
+                    self.list[i] = head;
+                    self.list.push(tail);
+                }
+            }
+            return Some(index);
+        }
+        None
+    }
+
+    fn dealloc_contiguous(&mut self, start: usize, count: usize) {
+        // 连续的页作为一个区间整体放回，而不是拆成单个页
+        self.debug_check_dealloc(start, start + count);
+        self.list.push((start, start + count));
+    }
+}
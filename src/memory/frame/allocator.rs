@@ -0,0 +1,104 @@
+// 帧分配器
+// 把分配算法给出的“页下标”换算成真实的物理地址，并包装成 [`FrameTracker`]。
+
+use super::algorithm::{Allocator, AllocatorImpl};
+use super::frame_tracker::FrameTracker;
+use crate::memory::{MemoryResult, address::*, config::*};
+use core::ops::Range;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    // 全局的帧分配器
+    // 管理范围：从内核结束地址（向上取整到页）一直到内存结束地址（向下取整到页）
+    // 用 Mutex 包装，保证同一时刻只有一处代码在修改分配器的状态
+    pub static ref FRAME_ALLOCATOR: Mutex<FrameAllocator<AllocatorImpl>> = Mutex::new(FrameAllocator::new(
+        PhysicalPageNumber::ceil(PhysicalAddress::try_from(*KERNEL_END_ADDRESS).unwrap())..free_memory_end()
+    ));
+}
+
+// 可以分配的物理内存的结束页号
+// QEMU 把设备树放在内存的末尾，设备树及其之后的物理页需要保留，不能交给帧分配器
+fn free_memory_end() -> PhysicalPageNumber {
+    let end = PhysicalPageNumber::floor(*MEMORY_END_ADDRESS);
+    let kernel_end = PhysicalAddress::try_from(*KERNEL_END_ADDRESS).unwrap();
+    match crate::fdt::get().map(|fdt| fdt.physical_range().start) {
+        Some(dtb) if kernel_end <= dtb => end.min(PhysicalPageNumber::floor(dtb)),
+        _ => end,
+    }
+}
+
+// 基于某种分配算法 `T` 的帧分配器
+pub struct FrameAllocator<T: Allocator> {
+    // 可用区间的起始页号
+    start_ppn: PhysicalPageNumber,
+    // 可用区间的页数
+    capacity: usize,
+    // 已经分配出去的页数
+    allocated: usize,
+    // 具体的分配算法
+    allocator: T,
+}
+
+impl<T: Allocator> FrameAllocator<T> {
+    // 创建对象，`range` 为可分配的物理页号区间
+    pub fn new(range: Range<PhysicalPageNumber>) -> Self {
+        FrameAllocator {
+            start_ppn: range.start,
+            capacity: range.end - range.start,
+            allocated: 0,
+            allocator: T::new(range.end - range.start),
+        }
+    }
+
+    // 管理的物理页总数
+    pub fn total_frames(&self) -> usize {
+        self.capacity
+    }
+
+    // 空闲的物理页数
+    pub fn free_frames(&self) -> usize {
+        self.capacity - self.allocated
+    }
+
+    // 分配一个物理页
+    pub fn alloc(&mut self) -> MemoryResult<FrameTracker> {
+        self.allocator
+            .alloc()
+            .ok_or("no available frame to allocate")
+            .map(|offset| {
+                self.allocated += 1;
+                FrameTracker(self.start_ppn + offset)
+            })
+    }
+
+    // 分配 `count` 个连续的物理页，起始物理页号按 `align` 个页对齐（`align` 为 2 的幂）
+    // 返回的物理页不受 [`FrameTracker`] 管理，需要调用者用 [`dealloc_contiguous`](Self::dealloc_contiguous) 归还
+    pub fn alloc_contiguous(
+        &mut self,
+        count: usize,
+        align: usize,
+    ) -> MemoryResult<Range<PhysicalPageNumber>> {
+        self.allocator
+            .alloc_contiguous(count, align, self.start_ppn.0 % align)
+            .ok_or("no available contiguous frames to allocate")
+            .map(|offset| {
+                self.allocated += count;
+                self.start_ppn + offset..self.start_ppn + offset + count
+            })
+    }
+
+    // 归还由 [`alloc_contiguous`](Self::alloc_contiguous) 分配的连续物理页
+    pub fn dealloc_contiguous(&mut self, range: Range<PhysicalPageNumber>) {
+        self.allocated -= range.end - range.start;
+        self.allocator
+            .dealloc_contiguous(range.start - self.start_ppn, range.end - range.start);
+    }
+
+    // 回收一个物理页
+    // 这个函数会在 [`FrameTracker`] 被 drop 时自动调用，不应在其他地方调用
+    pub(super) fn dealloc(&mut self, frame: &FrameTracker) {
+        self.allocated -= 1;
+        self.allocator.dealloc(frame.page_number() - self.start_ppn);
+    }
+}
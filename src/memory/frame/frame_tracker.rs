@@ -0,0 +1,78 @@
+// 物理页的 RAII 包装
+// 持有一个 [`FrameTracker`] 就代表“拥有”这一页物理内存；
+// 它离开作用域（被 drop）时会自动把物理页还给 [`FRAME_ALLOCATOR`]，不会出现忘记释放的情况。
+//
+// 一个帧也可以被多个 [`FrameTracker`] 共享（例如写时复制的页）：`clone` 只增加引用计数，
+// 最后一个持有者被 drop 时才真正释放物理页。
+
+use super::allocator::FRAME_ALLOCATOR;
+use crate::memory::{address::*, config::PAGE_SIZE};
+use alloc::collections::BTreeMap;
+use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+
+// 被共享的帧的引用计数
+// 只记录引用计数大于 1 的帧，不在表中的帧只有一个持有者
+static SHARED_FRAMES: Mutex<BTreeMap<PhysicalPageNumber, usize>> = Mutex::new(BTreeMap::new());
+
+// 分配出的物理页
+// 只能由 [`super::FrameAllocator`] 创建，因此内部字段仅对 frame 模块可见
+pub struct FrameTracker(pub(super) PhysicalPageNumber);
+
+impl FrameTracker {
+    // 帧的物理地址（页对齐）
+    pub fn address(&self) -> PhysicalAddress {
+        self.0.into()
+    }
+
+    // 帧的物理页号
+    pub fn page_number(&self) -> PhysicalPageNumber {
+        self.0
+    }
+
+    // 共享这个帧的 [`FrameTracker`] 个数
+    pub fn ref_count(&self) -> usize {
+        SHARED_FRAMES.lock().get(&self.0).copied().unwrap_or(1)
+    }
+}
+
+// 复制 FrameTracker 不会复制物理页，只是多了一个共享它的持有者
+// 共享的帧可能被其他持有者读到，写入之前应当先检查 `ref_count`
+impl Clone for FrameTracker {
+    fn clone(&self) -> Self {
+        *SHARED_FRAMES.lock().entry(self.0).or_insert(1) += 1;
+        Self(self.0)
+    }
+}
+
+// 可以像 `[u8; PAGE_SIZE]` 一样直接读写这一页的内容
+// 实际是通过内核的线性映射访问的
+impl Deref for FrameTracker {
+    type Target = [u8; PAGE_SIZE];
+    fn deref(&self) -> &Self::Target {
+        // 帧由帧分配器分出，位于线性映射之中
+        unsafe { self.page_number().deref_kernel() }
+    }
+}
+
+impl DerefMut for FrameTracker {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.page_number().deref_kernel() }
+    }
+}
+
+// 帧在释放时，放回分配器；仍然被共享时只减少引用计数
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        let mut shared = SHARED_FRAMES.lock();
+        if let Some(count) = shared.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 1 {
+                shared.remove(&self.0);
+            }
+            return;
+        }
+        drop(shared);
+        FRAME_ALLOCATOR.lock().dealloc(self);
+    }
+}
@@ -0,0 +1,19 @@
+// 物理页（帧）管理
+// 把内核结束之后、直到内存结束的全部物理内存按 4KB 切分成“帧”，
+// 通过 [`FRAME_ALLOCATOR`] 逐个分配出去，并用 [`FrameTracker`] 在释放时自动归还。
+
+mod algorithm;
+mod allocator;
+mod frame_tracker;
+
+#[allow(unused_imports)]
+pub use allocator::{FRAME_ALLOCATOR, FrameAllocator};
+#[allow(unused_imports)]
+pub use frame_tracker::FrameTracker;
+
+// 初始化帧分配器
+// lazy_static 默认在第一次使用时才初始化，这里提前触发，方便启动时就发现问题
+pub fn init() {
+    lazy_static::initialize(&FRAME_ALLOCATOR);
+    println!("mod frame initialized");
+}
@@ -0,0 +1,81 @@
+// 可失败的堆分配
+// `Box::new`、`Vec::push` 等接口在分配失败时会调用 `alloc_error_handler`，内核随之 panic。
+// 创建页表、地址空间或者缓冲区这样可能因为内存不足而失败的操作，应当改用这里的接口，
+// 把错误返回给调用者处理。分配失败之前，堆已经执行过 OOM 策略（见 `oom.rs`）。
+
+use crate::memory::MemoryResult;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+
+// 内存不足时返回的错误
+pub const OUT_OF_MEMORY: &str = "out of memory";
+
+// 把 `value` 放到堆上，内存不足时返回错误
+#[allow(dead_code)]
+pub fn try_box<T>(value: T) -> MemoryResult<Box<T>> {
+    let layout = Layout::new::<T>();
+    // 零大小的类型不需要分配
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+    let pointer = unsafe { alloc::alloc::alloc(layout) } as *mut T;
+    if pointer.is_null() {
+        return Err(OUT_OF_MEMORY);
+    }
+    unsafe {
+        pointer.write(value);
+        Ok(Box::from_raw(pointer))
+    }
+}
+
+// 申请能容纳 `capacity` 个元素的空 Vec
+pub fn try_vec_with_capacity<T>(capacity: usize) -> MemoryResult<Vec<T>> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(capacity).map_err(|_| OUT_OF_MEMORY)?;
+    Ok(vec)
+}
+
+// 申请 `length` 字节、内容全为 0 的缓冲区
+#[allow(dead_code)]
+pub fn try_zeroed_buffer(length: usize) -> MemoryResult<Vec<u8>> {
+    let mut buffer = try_vec_with_capacity(length)?;
+    buffer.resize(length, 0);
+    Ok(buffer)
+}
+
+// Vec 的可失败操作，容量不足时先尝试扩容，扩容失败则返回错误而不修改 Vec
+pub trait TryVec<T> {
+    // 预留至少 `additional` 个元素的空间
+    fn try_reserve_additional(&mut self, additional: usize) -> MemoryResult<()>;
+    // 在末尾加入一个元素
+    fn try_push(&mut self, value: T) -> MemoryResult<()>;
+    // 在末尾加入迭代器中的所有元素
+    fn try_extend<I>(&mut self, iter: I) -> MemoryResult<()>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator;
+}
+
+impl<T> TryVec<T> for Vec<T> {
+    fn try_reserve_additional(&mut self, additional: usize) -> MemoryResult<()> {
+        self.try_reserve(additional).map_err(|_| OUT_OF_MEMORY)
+    }
+
+    fn try_push(&mut self, value: T) -> MemoryResult<()> {
+        self.try_reserve_additional(1)?;
+        self.push(value);
+        Ok(())
+    }
+
+    fn try_extend<I>(&mut self, iter: I) -> MemoryResult<()>
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        self.try_reserve_additional(iter.len())?;
+        self.extend(iter);
+        Ok(())
+    }
+}
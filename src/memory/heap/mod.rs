@@ -0,0 +1,300 @@
+// 进行动态内存分配所用的堆空间
+// 堆一开始只有一块静态的启动堆，供帧分配器就绪之前使用（帧分配器本身也要用到 Vec）。
+// 之后每当空间不足，就从帧分配器借来一段连续的物理页加入堆中；
+// 这些页空闲时，还可以通过 [`shrink`] 还给帧分配器。
+// 较小的分配请求先经过 [`slab::SlabAllocator`]，由按大小分级的 slab 缓存满足。
+// 所有分配都经过 [`stats::TrackedAllocator`] 记录统计信息，可以通过 [`stats()`] 查询。
+// 开启 `heap_sanitizer` 功能后，每个内存块都加上红区，释放的内存块先进入隔离区（见 `sanitizer.rs`）。
+// 堆无法扩展时先执行 OOM 策略回收内存（见 `oom.rs`）；
+// 可能因内存不足而失败的操作应当使用 `fallible.rs` 中的接口，把错误返回给调用者。
+
+mod fallible;
+mod oom;
+#[cfg(feature = "heap_sanitizer")]
+pub mod sanitizer;
+mod slab;
+mod stats;
+
+#[allow(unused_imports)]
+pub use fallible::{OUT_OF_MEMORY, TryVec, try_box, try_vec_with_capacity, try_zeroed_buffer};
+#[allow(unused_imports)]
+pub use oom::{OomHandler, OomStats, oom_stats, register_oom_handler, unregister_oom_handler};
+
+#[allow(unused_imports)]
+pub use slab::{CacheBox, ObjectCache, SlabStats};
+pub use stats::{HeapStats, leak_report, stats};
+
+// 引入同级模块 config 里的堆大小定义
+use super::config::*;
+use super::address::*;
+use super::frame::FRAME_ALLOCATOR;
+// 引入外部分配器库
+use buddy_system_allocator::Heap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ops::Range;
+// 引入获取原始指针的宏
+use core::ptr::{addr_of_mut, null_mut, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use slab::SlabAllocator;
+use stats::TrackedAllocator;
+#[cfg(feature = "heap_sanitizer")]
+use sanitizer::SanitizedAllocator;
+
+// 在内存中预留出启动堆的连续空间
+// 这段空间在编译后会被放在 bss 段，意味着它不占用内核二进制文件的体积，
+// 但在程序加载到内存时会被初始化为 0。
+// 单独放在 .bss.heap 中，`linker.ld` 据此导出它的起止位置（见 `memory::layout`）。
+#[unsafe(link_section = ".bss.heap")]
+static mut BOOT_HEAP_SPACE: [u8; BOOT_HEAP_SIZE] = [0; BOOT_HEAP_SIZE];
+
+// 堆，动态内存分配器
+// ### `#[global_allocator]`
+// “以后凡是代码里用到 Box、Vec 等需要分内存的地方，都来找这个 HEAP 变量”。
+// KernelHeap 内部用锁保护伙伴系统分配器，保证了在多核或中断环境下分配内存是安全的。
+// 它前面是 SlabAllocator，小对象从 slab 缓存中分配；
+// 最外面再包一层 TrackedAllocator，用来统计每一次分配和释放。
+// 开启 `heap_sanitizer` 功能时，两者之间还有一层 SanitizedAllocator。
+#[cfg(not(feature = "heap_sanitizer"))]
+#[global_allocator]
+static HEAP: TrackedAllocator<SlabAllocator<KernelHeap>> =
+    TrackedAllocator::new(SlabAllocator::new(KernelHeap::new()));
+#[cfg(feature = "heap_sanitizer")]
+#[global_allocator]
+static HEAP: TrackedAllocator<SanitizedAllocator<SlabAllocator<KernelHeap>>> =
+    TrackedAllocator::new(SanitizedAllocator::new(SlabAllocator::new(KernelHeap::new())));
+
+// 按大小分级的 slab 分配器
+#[cfg(not(feature = "heap_sanitizer"))]
+fn slab_allocator() -> &'static SlabAllocator<KernelHeap> {
+    HEAP.inner()
+}
+#[cfg(feature = "heap_sanitizer")]
+fn slab_allocator() -> &'static SlabAllocator<KernelHeap> {
+    HEAP.inner().inner()
+}
+
+// 位于最下层的伙伴系统堆
+fn kernel_heap() -> &'static KernelHeap {
+    slab_allocator().backing()
+}
+
+// 最多能从帧分配器借来的区域个数
+// 用固定大小的数组记录，这样在扩展堆的过程中不需要再分配堆内存
+const MAX_REGIONS: usize = 64;
+
+// 堆的运行时配置
+#[derive(Debug, Copy, Clone)]
+pub struct HeapConfig {
+    // 初始化完成后，堆至少拥有的空间
+    pub initial_size: usize,
+    // 堆最多能扩展到的空间
+    pub max_size: usize,
+}
+
+impl Default for HeapConfig {
+    fn default() -> Self {
+        Self {
+            initial_size: KERNEL_HEAP_INITIAL_SIZE,
+            max_size: KERNEL_HEAP_MAX_SIZE,
+        }
+    }
+}
+
+// 从帧分配器借来的区域，每一项为 (起始物理页号, 页数)
+struct Regions {
+    list: [(PhysicalPageNumber, usize); MAX_REGIONS],
+    length: usize,
+}
+
+// 可以自动扩展的内核堆
+pub struct KernelHeap {
+    // 伙伴系统分配器
+    heap: Mutex<Heap>,
+    // 从帧分配器借来的区域
+    regions: Mutex<Regions>,
+    // 当前堆的总大小
+    size: AtomicUsize,
+    // 堆最多能扩展到的大小，在 [`configure`] 之前为 0，即不允许扩展
+    max_size: AtomicUsize,
+}
+
+impl KernelHeap {
+    const fn new() -> Self {
+        Self {
+            heap: Mutex::new(Heap::new()),
+            regions: Mutex::new(Regions {
+                list: [(PhysicalPageNumber(0), 0); MAX_REGIONS],
+                length: 0,
+            }),
+            size: AtomicUsize::new(0),
+            max_size: AtomicUsize::new(0),
+        }
+    }
+
+    // 从帧分配器借来 `size` 字节（2 的幂、页的整数倍）的连续空间加入堆中
+    // 这段空间按自身大小对齐，加入伙伴系统后恰好是一整块，能满足同样大小的分配请求
+    fn grow(&self, size: usize) -> Result<(), ()> {
+        if self.size.load(Ordering::Relaxed) + size > self.max_size.load(Ordering::Relaxed) {
+            return Err(());
+        }
+        let pages = size / PAGE_SIZE;
+        let mut regions = self.regions.lock();
+        if regions.length == MAX_REGIONS {
+            return Err(());
+        }
+        // 如果帧分配器正被持有（例如它在回收页时触发了堆分配），等待它只会死锁
+        let range = FRAME_ALLOCATOR
+            .try_lock()
+            .ok_or(())?
+            .alloc_contiguous(pages, pages)
+            .map_err(|_| ())?;
+        let length = regions.length;
+        regions.list[length] = (range.start, pages);
+        regions.length += 1;
+        drop(regions);
+        // 通过内核的线性映射访问这些物理页，所有地址空间中都能访问到
+        let start = VirtualAddress::from(PhysicalAddress::from(range.start)).0;
+        unsafe { self.heap.lock().add_to_heap(start, start + size) };
+        self.size.fetch_add(size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // 把完全空闲的借来区域还给帧分配器，返回释放的字节数
+    fn shrink(&self) -> usize {
+        // 先从堆中取出所有可以释放的区域，再统一归还，
+        // 因为归还物理页时帧分配器可能需要分配堆内存，此时不能持有堆相关的锁
+        let mut released: [Range<PhysicalPageNumber>; MAX_REGIONS] =
+            [const { PhysicalPageNumber(0)..PhysicalPageNumber(0) }; MAX_REGIONS];
+        let mut count = 0;
+        let mut regions = self.regions.lock();
+        for i in (0..regions.length).rev() {
+            let (start, pages) = regions.list[i];
+            let size = pages * PAGE_SIZE;
+            let address = VirtualAddress::from(PhysicalAddress::from(start)).0;
+            let layout = Layout::from_size_align(size, size).unwrap();
+            // 申请一块和区域同样大小、同样对齐的空间：
+            // 如果拿到的恰好是这个区域，说明它整块空闲，且已经从伙伴系统中取了出来
+            let mut heap = self.heap.lock();
+            match heap.alloc(layout) {
+                Ok(pointer) if pointer.as_ptr() as usize == address => {
+                    released[count] = start..start + pages;
+                    count += 1;
+                    let length = regions.length;
+                    regions.list.copy_within(i + 1..length, i);
+                    regions.length -= 1;
+                    self.size.fetch_sub(size, Ordering::Relaxed);
+                }
+                Ok(pointer) => heap.dealloc(pointer, layout),
+                Err(()) => {}
+            }
+        }
+        drop(regions);
+        let mut bytes = 0;
+        for range in released.into_iter().take(count) {
+            bytes += (range.end - range.start) * PAGE_SIZE;
+            FRAME_ALLOCATOR.lock().dealloc_contiguous(range);
+        }
+        bytes
+    }
+}
+
+impl KernelHeap {
+    // 从伙伴系统中分配，空间不足时扩展一次
+    fn alloc_or_grow(&self, layout: Layout) -> *mut u8 {
+        if let Ok(pointer) = self.heap.lock().alloc(layout) {
+            return pointer.as_ptr();
+        }
+        // 空间不足，扩展后再试一次（扩展时不持有堆的锁）
+        let size = layout
+            .size()
+            .max(layout.align())
+            .next_power_of_two()
+            .max(KERNEL_HEAP_GROW_STEP);
+        if self.grow(size).is_err() {
+            return null_mut();
+        }
+        self.heap
+            .lock()
+            .alloc(layout)
+            .map_or(null_mut(), |pointer| pointer.as_ptr())
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = self.alloc_or_grow(layout);
+        if !pointer.is_null() {
+            return pointer;
+        }
+        // 堆无法再扩展，执行 OOM 策略回收内存后重试
+        oom::handle(&layout, || self.alloc_or_grow(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.heap.lock().dealloc(NonNull::new_unchecked(ptr), layout) }
+    }
+}
+
+// 初始化操作系统运行时堆空间
+// 此时只有启动堆可用，帧分配器就绪后还需要调用 [`configure`]
+pub fn init() {
+    // 告诉分配器：这是启动时管辖的区间。
+    // 我们把 BOOT_HEAP_SPACE 的起始地址和大小传给它。
+    unsafe {
+        // 直接拿到 BOOT_HEAP_SPACE 的起始内存地址。
+        let heap_start = addr_of_mut!(BOOT_HEAP_SPACE) as usize;
+        kernel_heap().heap.lock().init(heap_start, BOOT_HEAP_SIZE);
+    }
+    kernel_heap().size.store(BOOT_HEAP_SIZE, Ordering::Relaxed);
+}
+
+// 设置堆的上限，并扩展到初始大小
+// 必须在帧分配器初始化之后调用，此后堆才能自动扩展
+pub fn configure(config: HeapConfig) {
+    kernel_heap().max_size.store(config.max_size, Ordering::Relaxed);
+    while size() < config.initial_size {
+        // 每次扩展的大小都是 2 的幂，取不超过缺口的最大值
+        let missing = config.initial_size - size();
+        let step = (1 << (usize::BITS - 1 - missing.leading_zeros())).max(KERNEL_HEAP_GROW_STEP);
+        kernel_heap()
+            .grow(step)
+            .expect("failed to grow the kernel heap to its initial size");
+    }
+    // 内存不足时，隔离区中的内存块可以提前释放
+    #[cfg(feature = "heap_sanitizer")]
+    register_oom_handler("quarantine", |_| sanitizer::drain(slab_allocator())).unwrap();
+}
+
+// 当前堆的总大小（字节）
+pub fn size() -> usize {
+    kernel_heap().size.load(Ordering::Relaxed)
+}
+
+// 把完全空闲的扩展区域还给帧分配器，返回释放的字节数
+// 先把分级缓存中的空 slab 还给伙伴系统，这样更多的区域能够整块空闲；启动堆不会被释放
+pub fn shrink() -> usize {
+    #[cfg(feature = "heap_sanitizer")]
+    sanitizer::drain(slab_allocator());
+    slab_allocator().reclaim();
+    kernel_heap().shrink()
+}
+
+// 各个分级 slab 缓存的使用情况
+pub fn slab_stats() -> impl Iterator<Item = SlabStats> {
+    slab_allocator().stats()
+}
+
+// 空间分配错误的回调
+// 如果堆已经扩展到上限（或者物理内存耗尽），OOM 策略也没能回收到足够的内存，
+// 不可失败的分配（`Box::new`、`Vec::push` 等）会自动调用这个函数。
+// 我们选择直接 panic 报错，防止程序带着错误的地址跑下去；需要处理内存不足的地方应当使用 `fallible.rs` 中的接口。
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    panic!(
+        "out of memory: cannot allocate {:#x} bytes aligned to {:#x} (heap size {:#x})",
+        layout.size(),
+        layout.align(),
+        size()
+    )
+}
@@ -0,0 +1,113 @@
+// 内存不足（OOM）时的处理策略
+// 堆无法再从帧分配器扩展时，先依次尝试各种回收手段，每回收到一些内存就重试一次分配，
+// 全部失败之后才让分配返回空指针：可失败的分配（见 `fallible.rs`）把错误交给调用者，
+// 其余的分配进入 `alloc_error_handler`。
+//
+// 回收的顺序：
+// 1. 把分级 slab 缓存中的空 slab 还给伙伴系统；
+// 2. 按注册顺序调用各个子系统的回收函数，例如释放文件缓存，
+//    最后一级可以由进程管理注册：杀死一个用户进程，释放它的全部内存。
+//    换出用户页需要获取帧分配器的锁，不能作为回收函数（见 `mapping/reclaim.rs`）。
+//
+// 回收函数在分配内存的上下文中运行，调用者可能持有任意的锁：
+// 回收函数只能用 `try_lock` 获取锁，拿不到就放弃。
+// 回收过程中再次分配失败不会重新进入回收，而是直接失败。
+
+use super::slab_allocator;
+use crate::memory::MemoryResult;
+use core::alloc::Layout;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+// 回收函数，参数为分配失败的请求，返回大约回收了多少字节
+pub type OomHandler = fn(&Layout) -> usize;
+
+// 最多能注册的回收函数个数
+// 用固定大小的数组记录，这样注册和回收时都不需要分配堆内存
+const MAX_HANDLERS: usize = 8;
+
+// 已注册的回收函数，按注册顺序排列
+static HANDLERS: Mutex<[Option<(&'static str, OomHandler)>; MAX_HANDLERS]> =
+    Mutex::new([None; MAX_HANDLERS]);
+// 是否正在回收
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+// 进入回收的次数、回收后分配成功的次数、累计回收的字节数
+static INVOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static RECOVERIES: AtomicUsize = AtomicUsize::new(0);
+static RECLAIMED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// OOM 处理的统计
+#[derive(Debug, Copy, Clone)]
+pub struct OomStats {
+    // 进入回收的次数
+    pub invocations: usize,
+    // 回收之后分配成功的次数
+    pub recoveries: usize,
+    // 累计回收的字节数
+    pub reclaimed_bytes: usize,
+}
+
+// 注册一个回收函数，排在已有的回收函数之后
+#[allow(dead_code)]
+pub fn register_oom_handler(name: &'static str, handler: OomHandler) -> MemoryResult<()> {
+    let mut handlers = HANDLERS.lock();
+    let slot = handlers
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or("too many OOM handlers")?;
+    *slot = Some((name, handler));
+    Ok(())
+}
+
+// 移除名为 `name` 的回收函数
+#[allow(dead_code)]
+pub fn unregister_oom_handler(name: &'static str) -> MemoryResult<()> {
+    let mut handlers = HANDLERS.lock();
+    let index = handlers
+        .iter()
+        .position(|slot| slot.is_some_and(|(n, _)| n == name))
+        .ok_or("OOM handler is not registered")?;
+    handlers.copy_within(index + 1.., index);
+    handlers[MAX_HANDLERS - 1] = None;
+    Ok(())
+}
+
+// 获取 OOM 处理的统计
+#[allow(dead_code)]
+pub fn oom_stats() -> OomStats {
+    OomStats {
+        invocations: INVOCATIONS.load(Ordering::Relaxed),
+        recoveries: RECOVERIES.load(Ordering::Relaxed),
+        reclaimed_bytes: RECLAIMED_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+// 执行 OOM 策略：逐级回收内存，每回收到一些就调用 `retry` 重新分配
+// 返回 `retry` 分配到的指针，所有回收手段都用尽时返回空指针
+pub(super) fn handle(layout: &Layout, mut retry: impl FnMut() -> *mut u8) -> *mut u8 {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return null_mut();
+    }
+    INVOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let mut pointer = null_mut();
+    let mut attempt = |reclaimed: usize| {
+        RECLAIMED_BYTES.fetch_add(reclaimed, Ordering::Relaxed);
+        if reclaimed != 0 {
+            pointer = retry();
+        }
+        !pointer.is_null()
+    };
+    // 复制一份回收函数的列表，调用时不持有锁，回收函数可以注册或移除回收函数
+    let handlers = *HANDLERS.lock();
+    let recovered = attempt(slab_allocator().reclaim())
+        || handlers
+            .iter()
+            .flatten()
+            .any(|(_, handler)| attempt(handler(layout)));
+    if recovered {
+        RECOVERIES.fetch_add(1, Ordering::Relaxed);
+    }
+    RECLAIMING.store(false, Ordering::Release);
+    pointer
+}
@@ -0,0 +1,349 @@
+// 堆的地址检查（`heap_sanitizer` 功能）
+// [`SanitizedAllocator`] 在每个内存块的前后加上填满毒值的红区（red zone），
+// 释放的内存块也填上毒值，先放进隔离区（quarantine），隔离区满了才真正释放最早的内存块。
+// 释放时、内存块离开隔离区时，以及调用 [`check`] 时检查毒值：
+// - 红区被改写：越界写入（overflow / underflow）
+// - 隔离区中的内存块被改写：释放后写入（use-after-free）
+// - 释放已经释放的内存块：重复释放（double free）
+// 发现错误时打印内存块的分配位置和释放位置（调用者的返回地址），然后 panic。
+// 没有影子内存和编译器插桩，越界读取和释放后读取是检查不出来的。
+//
+// 每个内存块的布局：
+// | 头部 | 左红区 | 用户数据（size 字节）| 右红区 |
+// 头部和左红区一共 `left` 字节，按请求的对齐向上取整，保证用户数据的对齐不变。
+
+use super::stats::callers;
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+// 左红区至少的字节数（不含头部），以及右红区的字节数
+const REDZONE: usize = 32;
+// 隔离区最多保留的字节数（包括红区），超过时释放最早进入的内存块
+const QUARANTINE_BYTES: usize = 0x4_0000;
+// 记录的调用栈深度
+const DEPTH: usize = 4;
+
+// 红区中的毒值
+const REDZONE_POISON: u8 = 0xfc;
+// 已释放内存中的毒值
+const FREED_POISON: u8 = 0xfd;
+
+// 头部中的魔数，标记内存块的状态
+const ALIVE: usize = 0x6b61_7361_6e41_4c56;
+const FREED: usize = 0x6b61_7361_6e46_5245;
+
+// 内存块的头部，位于左红区的开头
+// 存活的内存块和隔离区中的内存块分别用 `prev` / `next` 串成双向链表
+#[repr(C)]
+struct Header {
+    magic: usize,
+    // 用户请求的大小和对齐
+    size: usize,
+    align: usize,
+    prev: *mut Header,
+    next: *mut Header,
+    // 分配位置和释放位置
+    alloc_site: [usize; DEPTH],
+    free_site: [usize; DEPTH],
+}
+
+// 由头部串成的双向链表，从 `head` 插入，从 `tail` 取出
+struct BlockList {
+    head: *mut Header,
+    tail: *mut Header,
+    // 链表中内存块的总字节数（包括红区）
+    bytes: usize,
+}
+
+// 链表中的内存块只通过锁访问
+unsafe impl Send for BlockList {}
+
+impl BlockList {
+    const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            tail: null_mut(),
+            bytes: 0,
+        }
+    }
+
+    fn push(&mut self, header: *mut Header) {
+        unsafe {
+            (*header).prev = null_mut();
+            (*header).next = self.head;
+            if self.head.is_null() {
+                self.tail = header;
+            } else {
+                (*self.head).prev = header;
+            }
+            self.bytes += padded(&*header).size();
+        }
+        self.head = header;
+    }
+
+    fn remove(&mut self, header: *mut Header) {
+        unsafe {
+            let Header { prev, next, .. } = *header;
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if next.is_null() {
+                self.tail = prev;
+            } else {
+                (*next).prev = prev;
+            }
+            self.bytes -= padded(&*header).size();
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<*mut Header> {
+        let header = self.tail;
+        if header.is_null() {
+            return None;
+        }
+        self.remove(header);
+        Some(header)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Header> + '_ {
+        let mut header = self.head;
+        core::iter::from_fn(move || {
+            let current = unsafe { header.as_ref()? };
+            header = current.next;
+            Some(current)
+        })
+    }
+}
+
+// 存活的内存块
+static LIVE: Mutex<BlockList> = Mutex::new(BlockList::new());
+// 隔离区中的内存块
+static QUARANTINE: Mutex<BlockList> = Mutex::new(BlockList::new());
+// 已经检查过的内存块次数
+static CHECKS: AtomicUsize = AtomicUsize::new(0);
+
+// 头部和左红区的字节数
+fn left_size(align: usize) -> usize {
+    (size_of::<Header>() + REDZONE).next_multiple_of(align)
+}
+
+// 加上头部和红区之后，向下层分配器申请的大小和对齐
+fn padded_layout(size: usize, align: usize) -> Layout {
+    let align = align.max(align_of::<Header>());
+    Layout::from_size_align(left_size(align) + size + REDZONE, align).unwrap()
+}
+
+fn padded(header: &Header) -> Layout {
+    padded_layout(header.size, header.align)
+}
+
+// 内存块中的几个部分
+impl Header {
+    fn left_redzone(&self) -> &[u8] {
+        let start = self as *const Header as usize + size_of::<Header>();
+        let length = left_size(padded(self).align()) - size_of::<Header>();
+        unsafe { core::slice::from_raw_parts(start as *const u8, length) }
+    }
+
+    fn data(&self) -> *mut u8 {
+        (self as *const Header as usize + left_size(padded(self).align())) as *mut u8
+    }
+
+    fn user_data(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.data(), self.size) }
+    }
+
+    fn right_redzone(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.data().add(self.size), REDZONE) }
+    }
+}
+
+// 用户数据的地址对应的头部
+fn header_of(pointer: *mut u8, layout: Layout) -> *mut Header {
+    let align = padded_layout(layout.size(), layout.align()).align();
+    (pointer as usize - left_size(align)) as *mut Header
+}
+
+// 报告错误并 panic
+fn report(kind: &str, header: &Header, pointer: *const u8) -> ! {
+    println!("==== heap sanitizer: {} ====", kind);
+    println!(
+        "block {:#x} ({} bytes, align {}), bad byte at {:#x}",
+        header.data() as usize,
+        header.size,
+        header.align,
+        pointer as usize
+    );
+    println!("allocated from {:x?}", header.alloc_site);
+    if header.magic == FREED {
+        println!("freed from     {:x?}", header.free_site);
+    }
+    panic!("heap sanitizer: {}", kind)
+}
+
+// 第一个不等于 `poison` 的字节
+fn find_unpoisoned(bytes: &[u8], poison: u8) -> Option<*const u8> {
+    bytes
+        .iter()
+        .find(|&&byte| byte != poison)
+        .map(|byte| byte as *const u8)
+}
+
+// 检查一个内存块的毒值，隔离区中的内存块还要检查用户数据
+fn check_block(header: &Header) {
+    CHECKS.fetch_add(1, Ordering::Relaxed);
+    if let Some(bad) = find_unpoisoned(header.left_redzone(), REDZONE_POISON) {
+        report("heap-buffer-underflow", header, bad);
+    }
+    if let Some(bad) = find_unpoisoned(header.right_redzone(), REDZONE_POISON) {
+        report("heap-buffer-overflow", header, bad);
+    }
+    if header.magic == FREED
+        && let Some(bad) = find_unpoisoned(header.user_data(), FREED_POISON)
+    {
+        report("heap-use-after-free", header, bad);
+    }
+}
+
+// 检查所有存活的内存块和隔离区中的内存块，返回检查的内存块个数
+// 发现错误时 panic
+#[allow(dead_code)]
+pub fn check() -> usize {
+    let mut count = 0;
+    for list in [&LIVE, &QUARANTINE] {
+        for header in list.lock().iter() {
+            check_block(header);
+            count += 1;
+        }
+    }
+    count
+}
+
+// 清空隔离区，把其中的内存块还给下层分配器，返回释放的字节数
+// 作为 OOM 策略的回收函数时，调用者可能持有隔离区的锁，因此只尝试获取锁
+pub fn drain<A: GlobalAlloc>(backing: &A) -> usize {
+    let mut bytes = 0;
+    loop {
+        let header = match QUARANTINE.try_lock() {
+            Some(mut quarantine) => quarantine.pop_oldest(),
+            None => None,
+        };
+        let Some(header) = header else {
+            break;
+        };
+        bytes += unsafe { release(backing, header) };
+    }
+    bytes
+}
+
+// 检查隔离区中的内存块后把它还给下层分配器，返回释放的字节数
+unsafe fn release<A: GlobalAlloc>(backing: &A, header: *mut Header) -> usize {
+    let header_ref = unsafe { &*header };
+    check_block(header_ref);
+    let layout = padded(header_ref);
+    unsafe { backing.dealloc(header as *mut u8, layout) };
+    layout.size()
+}
+
+// 给内存块加上红区和隔离区的分配器包装
+pub struct SanitizedAllocator<A> {
+    inner: A,
+}
+
+impl<A> SanitizedAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    // 被包装的分配器
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for SanitizedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let padded = padded_layout(layout.size(), layout.align());
+        let header = unsafe { self.inner.alloc(padded) } as *mut Header;
+        if header.is_null() {
+            return null_mut();
+        }
+        unsafe {
+            header.write(Header {
+                magic: ALIVE,
+                size: layout.size(),
+                align: layout.align(),
+                prev: null_mut(),
+                next: null_mut(),
+                // 跳过 `callers` 和这个函数
+                alloc_site: callers(2),
+                free_site: [0; DEPTH],
+            });
+            let data = (*header).data();
+            let left = header as usize + size_of::<Header>();
+            (left as *mut u8).write_bytes(REDZONE_POISON, data as usize - left);
+            data.add(layout.size()).write_bytes(REDZONE_POISON, REDZONE);
+            LIVE.lock().push(header);
+            data
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let header = header_of(ptr, layout);
+        let header_ref = unsafe { &mut *header };
+        match header_ref.magic {
+            ALIVE => {}
+            FREED => report("double-free", header_ref, ptr),
+            _ => report("invalid-free or corrupted header", header_ref, ptr),
+        }
+        if header_ref.size != layout.size() || header_ref.align != layout.align() {
+            report("free with a mismatched layout", header_ref, ptr);
+        }
+        check_block(header_ref);
+        LIVE.lock().remove(header);
+        header_ref.magic = FREED;
+        header_ref.free_site = callers(2);
+        unsafe { ptr.write_bytes(FREED_POISON, layout.size()) };
+        // 放入隔离区，超出容量时取出最早的内存块，在锁外检查并释放
+        let mut quarantine = QUARANTINE.lock();
+        quarantine.push(header);
+        while quarantine.bytes > QUARANTINE_BYTES {
+            let Some(oldest) = quarantine.pop_oldest() else {
+                break;
+            };
+            drop(quarantine);
+            unsafe { release(&self.inner, oldest) };
+            quarantine = QUARANTINE.lock();
+        }
+    }
+}
+
+// 地址检查的统计
+#[derive(Debug, Copy, Clone)]
+pub struct SanitizerStats {
+    // 存活的内存块数
+    pub live_blocks: usize,
+    // 隔离区中的内存块数和字节数
+    pub quarantined_blocks: usize,
+    pub quarantined_bytes: usize,
+    // 累计检查内存块的次数
+    pub checks: usize,
+}
+
+// 获取地址检查的统计
+#[allow(dead_code)]
+pub fn stats() -> SanitizerStats {
+    let live_blocks = LIVE.lock().iter().count();
+    let quarantine = QUARANTINE.lock();
+    SanitizerStats {
+        live_blocks,
+        quarantined_blocks: quarantine.iter().count(),
+        quarantined_bytes: quarantine.bytes,
+        checks: CHECKS.load(Ordering::Relaxed),
+    }
+}
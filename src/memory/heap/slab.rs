@@ -0,0 +1,447 @@
+// Slab 分配器
+// 内核中的大多数对象大小固定，如果都交给伙伴系统，会被向上取整到 2 的幂，浪费不少空间。
+// Slab 分配器为每种对象大小维护一个缓存：从下层分配器申请整块的 slab，切成等大的对象，
+// 用空闲链表管理。较小的分配请求由按大小分级的缓存满足，其余的仍然交给伙伴系统。
+// 完全空闲的 slab 可以通过 `reclaim` 还给下层分配器。
+// 子系统也可以用 [`ObjectCache`] 为自己的类型建立专用的缓存。
+
+use super::super::MemoryResult;
+use crate::memory::config::PAGE_SIZE;
+use core::alloc::{GlobalAlloc, Layout};
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{NonNull, null_mut};
+use spin::Mutex;
+
+// 分级缓存的对象大小，超过最后一级的请求直接交给下层分配器
+const SIZE_CLASSES: [usize; 10] = [16, 32, 48, 64, 96, 128, 192, 256, 384, 512];
+// 每个缓存最多保留的空 slab 数，多出来的立即还给下层分配器
+const KEEP_EMPTY: usize = 1;
+// 一个 slab 至少能放下的对象数，据此确定 slab 的大小
+const MIN_OBJECTS: usize = 8;
+
+// 位于每个 slab 开头的头部
+// slab 按自身大小对齐，因此对象地址向下对齐到 slab 大小就能找到头部
+#[repr(C)]
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    // 空闲对象链表
+    free: *mut FreeObject,
+    // 正在使用的对象数
+    in_use: usize,
+}
+
+// 空闲对象的开头用来串成链表
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+// 由 slab 头部串成的双向链表
+struct SlabList {
+    head: *mut Slab,
+    length: usize,
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        Self {
+            head: null_mut(),
+            length: 0,
+        }
+    }
+
+    fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = null_mut();
+            (*slab).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).prev = slab;
+            }
+        }
+        self.head = slab;
+        self.length += 1;
+    }
+
+    fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            let Slab { prev, next, .. } = *slab;
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.length -= 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut Slab> {
+        let slab = self.head;
+        if slab.is_null() {
+            return None;
+        }
+        self.remove(slab);
+        Some(slab)
+    }
+}
+
+// 一个缓存中 slab 的状态
+struct Lists {
+    // 部分使用的 slab，分配时优先使用
+    partial: SlabList,
+    // 完全空闲的 slab
+    empty: SlabList,
+    // 已经用满的 slab 个数（不需要链表，释放对象时再放回 partial）
+    full: usize,
+}
+
+// 链表中的 slab 只通过锁访问
+unsafe impl Send for Lists {}
+
+// 某个缓存的使用情况
+#[derive(Debug, Copy, Clone)]
+pub struct SlabStats {
+    pub name: &'static str,
+    // 对象大小（字节）
+    pub object_size: usize,
+    // 每个 slab 的大小（字节）
+    pub slab_size: usize,
+    // slab 个数
+    pub slabs: usize,
+    // 正在使用的对象数
+    pub objects_in_use: usize,
+    // 所有 slab 能容纳的对象数
+    pub capacity: usize,
+}
+
+// 同一种大小的对象的缓存
+pub struct SlabCache {
+    name: &'static str,
+    object_size: usize,
+    slab_size: usize,
+    // 第一个对象相对 slab 起始地址的偏移
+    offset: usize,
+    // 每个 slab 能容纳的对象数
+    capacity: usize,
+    lists: Mutex<Lists>,
+}
+
+impl SlabCache {
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        let align = if align > align_of::<FreeObject>() {
+            align
+        } else {
+            align_of::<FreeObject>()
+        };
+        let size = if size > size_of::<FreeObject>() {
+            size
+        } else {
+            size_of::<FreeObject>()
+        };
+        let object_size = size.next_multiple_of(align);
+        let offset = size_of::<Slab>().next_multiple_of(align);
+        let mut slab_size = if align > PAGE_SIZE { align } else { PAGE_SIZE };
+        while slab_size < offset + MIN_OBJECTS * object_size {
+            slab_size *= 2;
+        }
+        Self {
+            name,
+            object_size,
+            slab_size,
+            offset,
+            capacity: (slab_size - offset) / object_size,
+            lists: Mutex::new(Lists {
+                partial: SlabList::new(),
+                empty: SlabList::new(),
+                full: 0,
+            }),
+        }
+    }
+
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+
+    // 把新申请的 slab 切成对象，串成空闲链表
+    unsafe fn init_slab(&self, slab: *mut Slab) {
+        let base = slab as usize + self.offset;
+        let mut free = null_mut();
+        for i in (0..self.capacity).rev() {
+            let object = (base + i * self.object_size) as *mut FreeObject;
+            unsafe { (*object).next = free };
+            free = object;
+        }
+        unsafe {
+            slab.write(Slab {
+                prev: null_mut(),
+                next: null_mut(),
+                free,
+                in_use: 0,
+            })
+        };
+    }
+
+    // 分配一个对象，没有空闲对象时从 `backing` 申请新的 slab
+    pub fn alloc<B: GlobalAlloc>(&self, backing: &B) -> *mut u8 {
+        loop {
+            let mut lists = self.lists.lock();
+            let slab = if !lists.partial.head.is_null() {
+                Some(lists.partial.head)
+            } else if let Some(slab) = lists.empty.pop() {
+                lists.partial.push(slab);
+                Some(slab)
+            } else {
+                None
+            };
+            if let Some(slab) = slab {
+                let slab = unsafe { &mut *slab };
+                let object = slab.free;
+                slab.free = unsafe { (*object).next };
+                slab.in_use += 1;
+                if slab.in_use == self.capacity {
+                    lists.partial.remove(slab);
+                    lists.full += 1;
+                }
+                return object as *mut u8;
+            }
+            // 申请新的 slab 时不持有锁，因为下层分配器扩展时可能再次分配堆内存
+            drop(lists);
+            let slab = unsafe { backing.alloc(self.slab_layout()) } as *mut Slab;
+            if slab.is_null() {
+                return null_mut();
+            }
+            unsafe { self.init_slab(slab) };
+            self.lists.lock().empty.push(slab);
+        }
+    }
+
+    // 释放一个对象，slab 变空后可能被还给 `backing`
+    pub fn dealloc<B: GlobalAlloc>(&self, backing: &B, pointer: *mut u8) {
+        let slab = (pointer as usize & !(self.slab_size - 1)) as *mut Slab;
+        let object = pointer as *mut FreeObject;
+        let mut lists = self.lists.lock();
+        let slab_ref = unsafe { &mut *slab };
+        if slab_ref.in_use == self.capacity {
+            lists.full -= 1;
+            lists.partial.push(slab);
+        }
+        unsafe { (*object).next = slab_ref.free };
+        slab_ref.free = object;
+        slab_ref.in_use -= 1;
+        if slab_ref.in_use == 0 {
+            lists.partial.remove(slab);
+            if lists.empty.length < KEEP_EMPTY {
+                lists.empty.push(slab);
+                return;
+            }
+            drop(lists);
+            unsafe { backing.dealloc(slab as *mut u8, self.slab_layout()) };
+        }
+    }
+
+    // 把所有空 slab 还给 `backing`，返回释放的字节数
+    pub fn reclaim<B: GlobalAlloc>(&self, backing: &B) -> usize {
+        let mut bytes = 0;
+        loop {
+            // 每次只在锁内取出一个，释放时不持有锁
+            let slab = self.lists.lock().empty.pop();
+            let Some(slab) = slab else {
+                break;
+            };
+            unsafe { backing.dealloc(slab as *mut u8, self.slab_layout()) };
+            bytes += self.slab_size;
+        }
+        bytes
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let lists = self.lists.lock();
+        let mut objects_in_use = lists.full * self.capacity;
+        let mut slab = lists.partial.head;
+        while !slab.is_null() {
+            unsafe {
+                objects_in_use += (*slab).in_use;
+                slab = (*slab).next;
+            }
+        }
+        let slabs = lists.partial.length + lists.empty.length + lists.full;
+        SlabStats {
+            name: self.name,
+            object_size: self.object_size,
+            slab_size: self.slab_size,
+            slabs,
+            objects_in_use,
+            capacity: slabs * self.capacity,
+        }
+    }
+}
+
+// 按大小分级的 slab 分配器，位于 `GlobalAlloc` 和下层分配器（伙伴系统）之间
+pub struct SlabAllocator<A> {
+    backing: A,
+    caches: [SlabCache; SIZE_CLASSES.len()],
+}
+
+// 分级缓存的对象按其大小的最低位对齐，例如 48 字节的对象按 16 字节对齐
+const fn class_align(size: usize) -> usize {
+    size & size.wrapping_neg()
+}
+
+macro_rules! size_class {
+    ($size: expr) => {
+        SlabCache::new(
+            concat!("size-", stringify!($size)),
+            $size,
+            class_align($size),
+        )
+    };
+}
+
+impl<A> SlabAllocator<A> {
+    pub const fn new(backing: A) -> Self {
+        Self {
+            backing,
+            caches: [
+                size_class!(16),
+                size_class!(32),
+                size_class!(48),
+                size_class!(64),
+                size_class!(96),
+                size_class!(128),
+                size_class!(192),
+                size_class!(256),
+                size_class!(384),
+                size_class!(512),
+            ],
+        }
+    }
+
+    // 下层分配器
+    pub fn backing(&self) -> &A {
+        &self.backing
+    }
+
+    // 能满足这个请求的分级缓存，大小和对齐都要满足
+    fn cache_for(&self, layout: Layout) -> Option<&SlabCache> {
+        SIZE_CLASSES
+            .iter()
+            .position(|&size| size >= layout.size() && class_align(size) >= layout.align())
+            .map(|index| &self.caches[index])
+    }
+
+    // 各个分级缓存的使用情况
+    pub fn stats(&self) -> impl Iterator<Item = SlabStats> + '_ {
+        self.caches.iter().map(SlabCache::stats)
+    }
+}
+
+impl<A: GlobalAlloc> SlabAllocator<A> {
+    // 把所有分级缓存中的空 slab 还给下层分配器，返回释放的字节数
+    pub fn reclaim(&self) -> usize {
+        self.caches
+            .iter()
+            .map(|cache| cache.reclaim(&self.backing))
+            .sum()
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for SlabAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.cache_for(layout) {
+            Some(cache) => cache.alloc(&self.backing),
+            None => unsafe { self.backing.alloc(layout) },
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.cache_for(layout) {
+            Some(cache) => cache.dealloc(&self.backing, ptr),
+            None => unsafe { self.backing.dealloc(ptr, layout) },
+        }
+    }
+}
+
+// 专用缓存的 slab 从全局堆申请，这样也会被计入堆的统计信息
+struct GlobalHeap;
+
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { alloc::alloc::dealloc(ptr, layout) }
+    }
+}
+
+// 某种类型的专用对象缓存
+// 一般定义为 `static`，例如 `static CONTEXTS: ObjectCache<Context> = ObjectCache::new("context");`
+pub struct ObjectCache<T> {
+    cache: SlabCache,
+    // 缓存本身不持有 `T`，用函数指针避免影响 Send / Sync
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ObjectCache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            cache: SlabCache::new(name, size_of::<T>(), align_of::<T>()),
+            _marker: PhantomData,
+        }
+    }
+
+    // 从缓存中分配一个对象，放入 `value`
+    pub fn alloc(&'static self, value: T) -> MemoryResult<CacheBox<T>> {
+        let pointer = NonNull::new(self.cache.alloc(&GlobalHeap) as *mut T)
+            .ok_or("no memory for object cache")?;
+        unsafe { pointer.as_ptr().write(value) };
+        Ok(CacheBox {
+            pointer,
+            cache: self,
+        })
+    }
+
+    // 把空 slab 还给堆，返回释放的字节数
+    pub fn reclaim(&self) -> usize {
+        self.cache.reclaim(&GlobalHeap)
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        self.cache.stats()
+    }
+}
+
+// 从 [`ObjectCache`] 中分配的对象，类似 `Box`，drop 时放回缓存
+pub struct CacheBox<T: 'static> {
+    pointer: NonNull<T>,
+    cache: &'static ObjectCache<T>,
+}
+
+unsafe impl<T: Send> Send for CacheBox<T> {}
+unsafe impl<T: Sync> Sync for CacheBox<T> {}
+
+impl<T> Deref for CacheBox<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.pointer.as_ref() }
+    }
+}
+
+impl<T> DerefMut for CacheBox<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.pointer.as_mut() }
+    }
+}
+
+impl<T> Drop for CacheBox<T> {
+    fn drop(&mut self) {
+        unsafe { self.pointer.as_ptr().drop_in_place() };
+        self.cache
+            .cache
+            .dealloc(&GlobalHeap, self.pointer.as_ptr() as *mut u8);
+    }
+}
@@ -0,0 +1,262 @@
+// 堆的统计信息与泄漏追踪
+// [`TrackedAllocator`] 包在真正的分配器外面，每次分配 / 释放时更新计数器：
+// 累计分配和释放的字节数、当前占用、历史峰值，以及按大小分桶的直方图。
+// 开启 `heap_tracking` 功能后，还会记录每个存活内存块的调用者地址，用于输出泄漏报告。
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// 直方图的桶数，第 i 个桶统计大小在 (2^(i-1), 2^i] 字节之间的分配
+pub const HISTOGRAM_BUCKETS: usize = 24;
+
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static FREED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static FAILURES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static HISTOGRAM: [AtomicUsize; HISTOGRAM_BUCKETS] =
+    [const { AtomicUsize::new(0) }; HISTOGRAM_BUCKETS];
+
+// 某一时刻堆的统计信息
+#[derive(Debug, Copy, Clone)]
+pub struct HeapStats {
+    // 堆当前的总大小（包括空闲部分）
+    pub heap_size: usize,
+    // 累计分配的字节数
+    pub allocated_bytes: usize,
+    // 累计释放的字节数
+    pub freed_bytes: usize,
+    // 当前仍在使用的字节数
+    pub live_bytes: usize,
+    // 历史上同时使用的最大字节数
+    pub peak_bytes: usize,
+    // 累计分配次数
+    pub allocations: usize,
+    // 累计释放次数
+    pub deallocations: usize,
+    // 分配失败的次数
+    pub failures: usize,
+    // 按大小分桶的分配次数
+    pub histogram: [usize; HISTOGRAM_BUCKETS],
+}
+
+impl HeapStats {
+    // 当前仍然存活的内存块个数
+    pub fn live_allocations(&self) -> usize {
+        self.allocations - self.deallocations
+    }
+}
+
+// 获取当前的统计信息
+pub fn stats() -> HeapStats {
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let freed_bytes = FREED_BYTES.load(Ordering::Relaxed);
+    HeapStats {
+        heap_size: super::size(),
+        allocated_bytes,
+        freed_bytes,
+        live_bytes: allocated_bytes - freed_bytes,
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        failures: FAILURES.load(Ordering::Relaxed),
+        histogram: core::array::from_fn(|i| HISTOGRAM[i].load(Ordering::Relaxed)),
+    }
+}
+
+// 打印统计信息和泄漏报告
+// 在关机前调用，此时仍然存活的内存块都被视为泄漏（内核中长期存在的对象也会出现在这里）
+pub fn leak_report() {
+    let stats = stats();
+    println!("--- kernel heap report ---");
+    println!(
+        "heap size: {:#x}, live: {:#x} bytes in {} blocks, peak: {:#x} bytes",
+        stats.heap_size,
+        stats.live_bytes,
+        stats.live_allocations(),
+        stats.peak_bytes
+    );
+    println!(
+        "allocated: {:#x} bytes in {} calls, freed: {:#x} bytes in {} calls, failures: {}",
+        stats.allocated_bytes,
+        stats.allocations,
+        stats.freed_bytes,
+        stats.deallocations,
+        stats.failures
+    );
+    for (i, count) in stats.histogram.iter().enumerate() {
+        if *count != 0 {
+            println!("  <= {:>8} bytes: {}", 1usize << i, count);
+        }
+    }
+    for cache in super::slab_stats().filter(|cache| cache.slabs != 0) {
+        println!(
+            "  slab {}: {}/{} objects in {} slabs",
+            cache.name, cache.objects_in_use, cache.capacity, cache.slabs
+        );
+    }
+    #[cfg(feature = "heap_tracking")]
+    tracking::report();
+}
+
+// 大小对应的直方图桶
+fn bucket(size: usize) -> usize {
+    let bucket = size.next_power_of_two().trailing_zeros() as usize;
+    bucket.min(HISTOGRAM_BUCKETS - 1)
+}
+
+// 带统计功能的分配器包装
+pub struct TrackedAllocator<A> {
+    inner: A,
+}
+
+impl<A> TrackedAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    // 被包装的分配器
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { self.inner.alloc(layout) };
+        if pointer.is_null() {
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+            return pointer;
+        }
+        let size = layout.size();
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        HISTOGRAM[bucket(size)].fetch_add(1, Ordering::Relaxed);
+        let allocated = ALLOCATED_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_BYTES.fetch_max(
+            allocated.saturating_sub(FREED_BYTES.load(Ordering::Relaxed)),
+            Ordering::Relaxed,
+        );
+        #[cfg(feature = "heap_tracking")]
+        tracking::insert(pointer as usize, size);
+        pointer
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap_tracking")]
+        tracking::remove(ptr as usize);
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        FREED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+}
+
+// 存活内存块的记录（`heap_tracking` 功能）
+// 记录表是固定大小的静态数组，记录时不会再分配堆内存
+#[cfg(feature = "heap_tracking")]
+mod tracking {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use spin::Mutex;
+
+    // 最多同时记录的存活内存块数
+    const CAPACITY: usize = 1024;
+    // 每个内存块记录的调用栈深度
+    const DEPTH: usize = 4;
+    // 空槽位和已删除槽位的标记（真实的堆地址不可能是 0 或 1）
+    const EMPTY: usize = 0;
+    const TOMBSTONE: usize = 1;
+
+    #[derive(Copy, Clone)]
+    struct Block {
+        address: usize,
+        size: usize,
+        callers: [usize; DEPTH],
+    }
+
+    const EMPTY_BLOCK: Block = Block {
+        address: EMPTY,
+        size: 0,
+        callers: [0; DEPTH],
+    };
+
+    // 以地址为键、线性探测的哈希表
+    static TABLE: Mutex<[Block; CAPACITY]> = Mutex::new([EMPTY_BLOCK; CAPACITY]);
+    // 记录表满了而没能记录的内存块数
+    static UNTRACKED: AtomicUsize = AtomicUsize::new(0);
+
+    fn slot(address: usize) -> usize {
+        (address >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) % CAPACITY
+    }
+
+    pub fn insert(address: usize, size: usize) {
+        // 跳过分配器自身的两层
+        let callers = super::callers(2);
+        let mut table = TABLE.lock();
+        let start = slot(address);
+        for i in 0..CAPACITY {
+            let block = &mut table[(start + i) % CAPACITY];
+            if block.address == EMPTY || block.address == TOMBSTONE {
+                *block = Block {
+                    address,
+                    size,
+                    callers,
+                };
+                return;
+            }
+        }
+        UNTRACKED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn remove(address: usize) {
+        let mut table = TABLE.lock();
+        let start = slot(address);
+        for i in 0..CAPACITY {
+            let block = &mut table[(start + i) % CAPACITY];
+            if block.address == address {
+                block.address = TOMBSTONE;
+                return;
+            }
+            if block.address == EMPTY {
+                return;
+            }
+        }
+    }
+
+    pub fn report() {
+        let table = TABLE.lock();
+        for block in table.iter().filter(|block| block.address > TOMBSTONE) {
+            println!(
+                "  live block {:#x} ({} bytes) allocated from {:x?}",
+                block.address, block.size, block.callers
+            );
+        }
+        let untracked = UNTRACKED.load(Ordering::Relaxed);
+        if untracked != 0 {
+            println!("  {} blocks were not tracked (table full)", untracked);
+        }
+    }
+}
+
+// 沿着帧指针（s0）回溯，得到调用者的返回地址，跳过最内层的 `skip` 层
+// RISC-V 的栈帧中，fp - 8 处保存返回地址，fp - 16 处保存上一层的 fp。
+// 只在当前栈的 64KB 范围内回溯，避免读到非法地址。
+#[cfg(any(feature = "heap_tracking", feature = "heap_sanitizer"))]
+pub(super) fn callers<const DEPTH: usize>(skip: usize) -> [usize; DEPTH] {
+    let mut callers = [0; DEPTH];
+    let (mut fp, sp): (usize, usize);
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+        core::arch::asm!("mv {}, sp", out(reg) sp);
+    }
+    for i in 0..DEPTH + skip {
+        if fp <= sp || fp - sp > 0x1_0000 || fp % 8 != 0 {
+            break;
+        }
+        let (ra, previous) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if i >= skip {
+            callers[i - skip] = ra;
+        }
+        fp = previous;
+    }
+    callers
+}
@@ -0,0 +1,149 @@
+// 内核的内存布局
+// 从 `linker.ld` 和 `entry.asm` 导出的符号中读出内核镜像各个部分的位置，
+// 再加上物理内存的范围、堆的大小和空闲帧的个数。
+// 启动时打印一次（见 `rust_main`），之后也可以随时调用 [`print`]，方便比较不同版本之间内核镜像的增长。
+
+use super::{address::*, config::*, frame::FRAME_ALLOCATOR, heap};
+use core::ops::Range;
+
+unsafe extern "C" {
+    // 由 `linker.ld` 导出
+    fn kernel_start();
+    fn text_start();
+    fn text_end();
+    fn rodata_start();
+    fn rodata_end();
+    fn data_start();
+    fn data_end();
+    fn bss_start();
+    fn boot_heap_start();
+    fn boot_heap_end();
+    fn bss_end();
+    fn kernel_end();
+    // 由 `entry.asm` 导出
+    fn boot_stack_guard();
+    fn boot_stack();
+    fn boot_stack_top();
+}
+
+// 把导出的符号转换为虚拟地址
+fn symbol(f: unsafe extern "C" fn()) -> VirtualAddress {
+    VirtualAddress(f as *const () as usize)
+}
+
+// 内核镜像中的一个区域
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: &'static str,
+    pub range: Range<VirtualAddress>,
+}
+
+impl Region {
+    // 区域的大小（字节）
+    pub fn size(&self) -> usize {
+        self.range.end - self.range.start
+    }
+}
+
+// 内核的内存布局
+#[derive(Debug, Clone)]
+pub struct Layout {
+    // 整个内核镜像（按页对齐）
+    pub kernel: Range<VirtualAddress>,
+    // 内核镜像中的各个区域，按地址排列；启动堆位于 .bss 之中
+    pub regions: [Region; 7],
+    // 内核所在的物理内存
+    pub memory: Range<PhysicalAddress>,
+    // 堆当前的大小（字节），包括启动堆和从帧分配器借来的部分
+    pub heap_size: usize,
+    // 帧分配器管理的物理页总数和空闲的物理页数
+    pub total_frames: usize,
+    pub free_frames: usize,
+}
+
+// 读取当前的内存布局
+pub fn layout() -> Layout {
+    let region = |name, start, end| Region {
+        name,
+        range: symbol(start)..symbol(end),
+    };
+    let (total_frames, free_frames) = {
+        let allocator = FRAME_ALLOCATOR.lock();
+        (allocator.total_frames(), allocator.free_frames())
+    };
+    Layout {
+        kernel: symbol(kernel_start)..symbol(kernel_end),
+        regions: [
+            region(".text", text_start, text_end),
+            region(".rodata", rodata_start, rodata_end),
+            region(".data", data_start, data_end),
+            region("stack guard", boot_stack_guard, boot_stack),
+            region("boot stack", boot_stack, boot_stack_top),
+            region(".bss", bss_start, bss_end),
+            region("  boot heap", boot_heap_start, boot_heap_end),
+        ],
+        memory: *MEMORY_START_ADDRESS..*MEMORY_END_ADDRESS,
+        heap_size: heap::size(),
+        total_frames,
+        free_frames,
+    }
+}
+
+// 以 KiB / MiB 为单位显示大小
+struct Size(usize);
+
+impl core::fmt::Display for Size {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            size if size >= 0x10_0000 => write!(f, "{:>4} MiB", size >> 20),
+            size if size >= 0x400 => write!(f, "{:>4} KiB", size >> 10),
+            size => write!(f, "{:>4} B  ", size),
+        }
+    }
+}
+
+// 打印内存布局表
+pub fn print() {
+    let layout = layout();
+    println!("+--------------------- kernel memory layout ----------------------+");
+    for region in &layout.regions {
+        println!(
+            "| {:<14} {:#x} - {:#x} {} |",
+            region.name,
+            region.range.start.0,
+            region.range.end.0,
+            Size(region.size())
+        );
+    }
+    println!(
+        "| {:<14} {:#x} - {:#x} {} |",
+        "kernel",
+        layout.kernel.start.0,
+        layout.kernel.end.0,
+        Size(layout.kernel.end - layout.kernel.start)
+    );
+    println!("+-----------------------------------------------------------------+");
+    println!(
+        "| {:<14} {:#18x} - {:#18x} {} |",
+        "memory",
+        layout.memory.start.0,
+        layout.memory.end.0,
+        Size(layout.memory.end.0 - layout.memory.start.0)
+    );
+    println!(
+        "| {:<14} {:>48} |",
+        "heap",
+        alloc::format!("{}", Size(layout.heap_size)).trim_end()
+    );
+    println!(
+        "| {:<14} {:>48} |",
+        "free frames",
+        alloc::format!(
+            "{} / {} ({})",
+            layout.free_frames,
+            layout.total_frames,
+            Size(layout.free_frames * PAGE_SIZE)
+        )
+    );
+    println!("+-----------------------------------------------------------------+");
+}
@@ -0,0 +1,108 @@
+// 写时复制（copy-on-write）
+// [`MemorySet::fork`] 复制地址空间时，用户段中已经分配的物理页并不复制，而是由父子双方共享同一个帧，
+// 双方页表中的写权限都被去掉。任何一方写入时触发 StorePageFault，这时才复制这一页；
+// 如果帧已经只剩下一个持有者，就不用复制，直接恢复写权限。
+// 段本身的权限保持不变：段允许写入而页表项没有写权限，就说明这一页正处于写时复制状态。
+//
+// 内核的段（没有 USER 标志）不参与写时复制：线性映射和设备映射直接建立相同的映射，
+// 按帧分配的段（例如内核栈）立即复制，否则内核写自己的栈时会在陷入处理的过程中再次陷入。
+
+use super::mapper::Mapping;
+use super::memory_set::MemorySet;
+use super::page_table_entry::Flags;
+use super::segment::MapType;
+use crate::memory::{
+    MemoryResult,
+    address::*,
+    frame::{FRAME_ALLOCATOR, FrameTracker},
+    heap::try_vec_with_capacity,
+};
+
+impl MemorySet {
+    // 复制出一个新的地址空间，用户段中已经分配的页与原地址空间写时共享
+    pub fn fork(&mut self) -> MemoryResult<MemorySet> {
+        let mut segments = try_vec_with_capacity(self.segments.len())?;
+        segments.extend_from_slice(&self.segments);
+        let mut child = MemorySet {
+            mapping: Mapping::new()?,
+            segments,
+            allocated_pairs: try_vec_with_capacity(self.allocated_pairs.len())?,
+            swap_slots: try_vec_with_capacity(self.swap_slots.len())?,
+            clock_hand: 0,
+        };
+        for segment in &self.segments {
+            if matches!(segment.map_type, MapType::Linear | MapType::Device) {
+                child.mapping.map(segment)?;
+            }
+        }
+        super::kernel_stack::share_stacks(&mut child.mapping);
+        for index in 0..self.allocated_pairs.len() {
+            let vpn = self.allocated_pairs[index].0;
+            let flags = self
+                .segments
+                .iter()
+                .find(|segment| segment.page_range().contains(&vpn))
+                .ok_or("allocated page is not in any segment")?
+                .flags;
+            let frame = if flags.contains(Flags::USER) {
+                self.allocated_pairs[index].1.clone()
+            } else {
+                copy_frame(&self.allocated_pairs[index].1)?
+            };
+            // 被 mprotect 取消了所有权限的页暂时没有映射，只共享帧
+            if self.mapping.lookup(vpn).is_some() {
+                let flags = if flags.contains(Flags::USER) {
+                    let flags = flags - Flags::WRITABLE;
+                    self.mapping.update_flags(vpn, flags)?;
+                    flags
+                } else {
+                    flags
+                };
+                child.mapping.map_one(vpn, frame.page_number(), flags)?;
+            }
+            child.allocated_pairs.push((vpn, frame));
+        }
+        // 已经换出的页，子地址空间在交换区中有自己的副本
+        for (vpn, slot) in &self.swap_slots {
+            if !self.allocated_pairs.iter().any(|(v, _)| v == vpn) {
+                child.swap_slots.push((*vpn, slot.duplicate()?));
+            }
+        }
+        Ok(child)
+    }
+
+    // 处理对写时复制的页的写入，`flags` 为所在段的权限
+    pub(super) fn handle_cow_fault(
+        &mut self,
+        vpn: VirtualPageNumber,
+        flags: Flags,
+    ) -> MemoryResult<()> {
+        let frame = self
+            .allocated_pairs
+            .iter_mut()
+            .find(|(v, _)| *v == vpn)
+            .map(|(_, frame)| frame)
+            .ok_or("write to a read-only page")?;
+        // 还有其他持有者时复制一份，原来的帧留给它们
+        if frame.ref_count() > 1 {
+            *frame = copy_frame(frame)?;
+        }
+        let ppn = frame.page_number();
+        self.mapping.remap_one(vpn, ppn, flags)
+    }
+
+    // 映射某一页时实际使用的权限：帧仍被共享时去掉写权限
+    pub(super) fn cow_flags(&self, vpn: VirtualPageNumber, flags: Flags) -> Flags {
+        match self.allocated_pairs.iter().find(|(v, _)| *v == vpn) {
+            Some((_, frame)) if frame.ref_count() > 1 => flags - Flags::WRITABLE,
+            _ => flags,
+        }
+    }
+}
+
+// 分配一个新的帧，复制 `frame` 的内容
+fn copy_frame(frame: &FrameTracker) -> MemoryResult<FrameTracker> {
+    let mut copy = FRAME_ALLOCATOR.lock().alloc()?;
+    copy.copy_from_slice(&frame[..]);
+    Ok(copy)
+}
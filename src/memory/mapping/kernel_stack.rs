@@ -0,0 +1,137 @@
+// 内核栈
+// 每个内核栈都位于一个大小为 2 * KERNEL_STACK_SIZE、按自身大小对齐的槽位中：
+// 上半部分是栈本身，下半部分不映射，作为保护区域。
+// 栈溢出时首先会访问到保护区域，触发缺页异常而不是悄悄破坏其他数据；
+// 同时陷入时只需检查栈指针的一位就能判断它是否已经落入保护区域（见 `interrupt.asm`）。
+//
+// 启动栈由 `entry.asm` 按同样的布局预留在内核镜像中，
+// 其余内核栈在 KERNEL_STACK_AREA_START 开始的区域中按需分配。
+//
+// 从用户态陷入时不切换 satp 就直接换到内核栈上，因此每个地址空间中都要能访问所有的内核栈。
+// 内核栈所在的 1GB 区域由一个单独的映射持有，每个地址空间创建时共用它的子树（见 [`share_stacks`]），
+// 之后分配和释放内核栈时只修改这棵子树，对所有存在的地址空间同时生效。
+
+use super::mapper::Mapping;
+use super::page_table_entry::Flags;
+use super::segment::{MapType, Segment};
+use super::tlb;
+use crate::memory::{MemoryResult, address::*, config::*, frame::FrameTracker};
+use alloc::vec::Vec;
+use core::ops::Range;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+unsafe extern "C" {
+    // 由 `entry.asm` 预留的启动栈及其保护区域
+    fn boot_stack_guard();
+    fn boot_stack();
+}
+
+// 每个槽位的大小
+const SLOT_SIZE: usize = 2 * KERNEL_STACK_SIZE;
+
+// 所有槽位都位于同一个 1GB 区域中
+const _: () = assert!(
+    KERNEL_STACK_AREA_START.0 % (1 << 30) + MAX_KERNEL_STACKS * SLOT_SIZE <= 1 << 30
+);
+
+// 已经使用的槽位，第 i 位为 1 表示第 i 个槽位正在使用
+static USED_SLOTS: Mutex<u64> = Mutex::new(0);
+
+lazy_static! {
+    // 持有内核栈所在区域的映射，只用来修改这个区域中的页表，不会被激活
+    static ref STACKS: Mutex<Mapping> = {
+        let mut mapping = Mapping::new().unwrap();
+        mapping.prepare_region(area_page()).unwrap();
+        Mutex::new(mapping)
+    };
+}
+
+fn area_page() -> VirtualPageNumber {
+    VirtualPageNumber::floor(KERNEL_STACK_AREA_START)
+}
+
+// 让新建的映射共用内核栈所在的区域，已经分配和之后分配的内核栈都可以访问
+pub(super) fn share_stacks(mapping: &mut Mapping) {
+    mapping.share_region(&STACKS.lock(), area_page());
+}
+
+// 启动栈下方的保护区域
+pub fn boot_stack_guard_range() -> Range<VirtualAddress> {
+    VirtualAddress(boot_stack_guard as *const () as usize)
+        ..VirtualAddress(boot_stack as *const () as usize)
+}
+
+// 地址是否位于某个内核栈的保护区域中
+pub fn is_guard_page(va: VirtualAddress) -> bool {
+    if boot_stack_guard_range().contains(&va) {
+        return true;
+    }
+    let area_end = KERNEL_STACK_AREA_START + MAX_KERNEL_STACKS * SLOT_SIZE;
+    (KERNEL_STACK_AREA_START..area_end).contains(&va)
+        && (va - KERNEL_STACK_AREA_START) % SLOT_SIZE < KERNEL_STACK_SIZE
+}
+
+// 动态分配的内核栈，drop 时从所有地址空间中解除映射并释放物理页
+pub struct KernelStack {
+    slot: usize,
+    segment: Segment,
+    // 栈的物理页，字段在 `drop` 之后才释放，此时已经解除了映射
+    _frames: Vec<(VirtualPageNumber, FrameTracker)>,
+}
+
+impl KernelStack {
+    // 分配一个内核栈，映射到所有的地址空间中
+    pub fn new() -> MemoryResult<Self> {
+        let slot = {
+            let mut used = USED_SLOTS.lock();
+            let slot = (!*used).trailing_zeros() as usize;
+            if slot >= MAX_KERNEL_STACKS {
+                return Err("no free kernel stack slot");
+            }
+            *used |= 1 << slot;
+            slot
+        };
+        let bottom = KERNEL_STACK_AREA_START + slot * SLOT_SIZE + KERNEL_STACK_SIZE;
+        let segment = Segment {
+            map_type: MapType::Framed,
+            range: bottom..bottom + KERNEL_STACK_SIZE,
+            flags: Flags::READABLE | Flags::WRITABLE,
+        };
+        let frames = match STACKS.lock().map(&segment) {
+            Ok(frames) => frames,
+            Err(error) => {
+                *USED_SLOTS.lock() &= !(1 << slot);
+                return Err(error);
+            }
+        };
+        // 其他 hart 上也可能要用到这个栈，例如从用户态陷入时
+        tlb::shootdown_all(segment.page_range());
+        Ok(Self {
+            slot,
+            segment,
+            _frames: frames,
+        })
+    }
+
+    // 栈顶（最高地址），作为初始的栈指针
+    pub fn top(&self) -> VirtualAddress {
+        self.segment.range.end
+    }
+
+    // 栈可以使用的地址区间
+    pub fn range(&self) -> Range<VirtualAddress> {
+        self.segment.range.clone()
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        STACKS
+            .lock()
+            .unmap(&self.segment)
+            .expect("kernel stack is not mapped");
+        tlb::shootdown_all(self.segment.page_range());
+        *USED_SLOTS.lock() &= !(1 << self.slot);
+    }
+}
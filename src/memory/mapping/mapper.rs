@@ -0,0 +1,395 @@
+// 一棵完整的 Sv39 页表树
+// [`Mapping`] 持有根页表以及所有用到的子页表，负责页的映射、取消映射、地址翻译，
+// 以及把自己写入 `satp` 寄存器使之生效。
+//
+// 除了 4KB 的页，Sv39 还允许第一、二级页表中的页表项直接作为叶子节点，
+// 映射 1GB 的大页（gigapage）或 2MB 的大页（megapage）。线性映射和设备映射在对齐允许时
+// 自动使用大页；之后如果只修改或取消大页中的一部分，会先把大页拆成下一级的页。
+// 下文用“层级”表示页表项所在的页表：0 为根页表（1GB），1 为第二级（2MB），2 为第三级（4KB）。
+
+use super::page_table::{PageTable, PageTableTracker};
+use super::page_table_entry::{Flags, PageTableEntry};
+use super::segment::{MapType, Segment};
+use super::tlb::TlbContext;
+use crate::memory::{
+    MemoryResult,
+    address::*,
+    config::PAGE_SIZE,
+    frame::{FRAME_ALLOCATOR, FrameTracker},
+    heap::{TryVec, try_vec_with_capacity},
+};
+use alloc::vec::Vec;
+use riscv::register::satp;
+
+// 第三级页表所在的层级
+const PAGE_LEVEL: usize = 2;
+
+// 某一层级的叶子页表项映射的页数（以 4KB 为单位）
+fn pages_at(level: usize) -> usize {
+    1 << (9 * (PAGE_LEVEL - level))
+}
+
+// 某个进程（或内核）的内存映射关系
+pub struct Mapping {
+    // 保存所有使用到的页表，drop 时会一并释放这些页表占用的物理页
+    page_tables: Vec<PageTableTracker>,
+    // 根页表的物理页号
+    root_ppn: PhysicalPageNumber,
+    // ASID 以及可能缓存了这个映射的 hart
+    // 映射被 drop 之后，本代中它的 ASID 不会再分配出去，TLB 中残留的条目也就不会被用到
+    tlb: TlbContext,
+}
+
+impl Mapping {
+    // 创建一个有根节点的映射
+    pub fn new() -> MemoryResult<Mapping> {
+        let root_table = PageTableTracker::new(FRAME_ALLOCATOR.lock().alloc()?);
+        let root_ppn = root_table.page_number();
+        let mut page_tables = try_vec_with_capacity(1)?;
+        page_tables.push(root_table);
+        Ok(Mapping {
+            page_tables,
+            root_ppn,
+            tlb: TlbContext::new(),
+        })
+    }
+
+    // 根页表的物理页号
+    #[allow(dead_code)]
+    pub fn root_ppn(&self) -> PhysicalPageNumber {
+        self.root_ppn
+    }
+
+    // 为 `vpn` 所在的 1GB 区域预先创建第二级页表，之后其他映射可以通过 [`share_region`](Self::share_region) 共用这棵子树
+    pub fn prepare_region(&mut self, vpn: VirtualPageNumber) -> MemoryResult<()> {
+        self.find_entry(vpn, 1).map(|_| ())
+    }
+
+    // 让 `vpn` 所在的 1GB 区域与 `other` 共用同一棵子树：直接复制根页表中的页表项
+    // 子树中的页表仍由 `other` 持有，之后 `other` 在这个区域中的修改在这里同样可见
+    pub fn share_region(&mut self, other: &Mapping, vpn: VirtualPageNumber) {
+        let index = vpn.levels()[0];
+        let root_table: &mut PageTable =
+            unsafe { PhysicalAddress::from(self.root_ppn).deref_kernel() };
+        let other_table: &PageTable =
+            unsafe { PhysicalAddress::from(other.root_ppn).deref_kernel() };
+        root_table.entries[index] = other_table.entries[index];
+    }
+
+    // 找到给定虚拟页号在 `level` 层级的页表项，途中缺少的页表会被创建
+    fn find_entry(
+        &mut self,
+        vpn: VirtualPageNumber,
+        level: usize,
+    ) -> MemoryResult<&'static mut PageTableEntry> {
+        // 从根页表开始向下查询
+        // 这里不用 self.page_tables[0] 避免后面产生 borrow-check 冲突
+        let root_table: &mut PageTable =
+            unsafe { PhysicalAddress::from(self.root_ppn).deref_kernel() };
+        let mut entry = &mut root_table.entries[vpn.levels()[0]];
+        for vpn_slice in &vpn.levels()[1..=level] {
+            if entry.is_empty() {
+                // 如果页表不存在，则需要分配一个新的页表
+                let new_table = PageTableTracker::new(FRAME_ALLOCATOR.lock().alloc()?);
+                let new_ppn = new_table.page_number();
+                // 先保存页表，保存失败时页表随之释放，页表项保持不变
+                self.page_tables.try_push(new_table)?;
+                // 将新页表的页号写入当前的页表项
+                *entry = PageTableEntry::new(Some(new_ppn), Flags::VALID);
+            } else if !entry.has_next_level() {
+                return Err("virtual address is covered by a leaf entry of an upper level");
+            }
+            // 进入下一级页表
+            entry = &mut entry.get_next_table().entries[*vpn_slice];
+        }
+        // 此时 entry 位于 `level` 层级的页表
+        Ok(entry)
+    }
+
+    // 找到映射给定虚拟页号的叶子页表项及其层级，不会创建新的页表
+    fn find_leaf(&self, vpn: VirtualPageNumber) -> Option<(&'static mut PageTableEntry, usize)> {
+        let root_table: &mut PageTable =
+            unsafe { PhysicalAddress::from(self.root_ppn).deref_kernel() };
+        let mut entry = &mut root_table.entries[vpn.levels()[0]];
+        for level in 0..PAGE_LEVEL {
+            if !entry.is_valid() {
+                return None;
+            }
+            if !entry.has_next_level() {
+                return Some((entry, level));
+            }
+            entry = &mut entry.get_next_table().entries[vpn.levels()[level + 1]];
+        }
+        entry.is_valid().then_some((entry, PAGE_LEVEL))
+    }
+
+    // 能否在 `level` 层级为给定的虚拟页号建立叶子页表项：途中不能有叶子节点，目标页表项必须为空
+    fn is_free_at(&self, vpn: VirtualPageNumber, level: usize) -> bool {
+        let root_table: &mut PageTable =
+            unsafe { PhysicalAddress::from(self.root_ppn).deref_kernel() };
+        let mut entry = &root_table.entries[vpn.levels()[0]];
+        for vpn_slice in &vpn.levels()[1..=level] {
+            if entry.is_empty() {
+                // 下面的页表还不存在，之后会被创建
+                return true;
+            }
+            if !entry.has_next_level() {
+                return false;
+            }
+            entry = &entry.get_next_table().entries[*vpn_slice];
+        }
+        entry.is_empty()
+    }
+
+    // 为给定的虚拟页号建立到物理页号的映射
+    pub fn map_one(
+        &mut self,
+        vpn: VirtualPageNumber,
+        ppn: PhysicalPageNumber,
+        flags: Flags,
+    ) -> MemoryResult<()> {
+        self.map_at(vpn, ppn, PAGE_LEVEL, flags)
+    }
+
+    // 在 `level` 层级建立一个叶子页表项，映射一个 4KB 的页或大页
+    // 大页的虚拟页号和物理页号都必须按大页的大小对齐
+    fn map_at(
+        &mut self,
+        vpn: VirtualPageNumber,
+        ppn: PhysicalPageNumber,
+        level: usize,
+        flags: Flags,
+    ) -> MemoryResult<()> {
+        if !vpn.0.is_multiple_of(pages_at(level)) || !ppn.0.is_multiple_of(pages_at(level)) {
+            return Err("huge page is not aligned");
+        }
+        // 定位到页表项
+        let entry = self.find_entry(vpn, level)?;
+        if !entry.is_empty() {
+            return Err("virtual address is already mapped");
+        }
+        // 页表项为空，则写入内容
+        *entry = PageTableEntry::new(Some(ppn), flags | Flags::VALID);
+        Ok(())
+    }
+
+    // 从 `vpn` 开始、不超过 `end` 的区域，最大能用哪一层级的页来映射到 `ppn`
+    fn best_level(
+        &self,
+        vpn: VirtualPageNumber,
+        ppn: PhysicalPageNumber,
+        end: VirtualPageNumber,
+    ) -> usize {
+        (0..PAGE_LEVEL)
+            .find(|&level| {
+                let pages = pages_at(level);
+                vpn.0.is_multiple_of(pages)
+                    && ppn.0.is_multiple_of(pages)
+                    && end - vpn >= pages
+                    && self.is_free_at(vpn, level)
+            })
+            .unwrap_or(PAGE_LEVEL)
+    }
+
+    // 把映射 `vpn` 的大页拆成下一级的页，权限不变
+    // 返回拆分后映射 `vpn` 的叶子页表项，已经是 4KB 的页时直接返回
+    fn split(&mut self, vpn: VirtualPageNumber) -> MemoryResult<&'static mut PageTableEntry> {
+        loop {
+            let (entry, level) = self.find_leaf(vpn).ok_or("virtual address is not mapped")?;
+            if level == PAGE_LEVEL {
+                return Ok(entry);
+            }
+            self.page_tables.try_reserve_additional(1)?;
+            let mut table = PageTableTracker::new(FRAME_ALLOCATOR.lock().alloc()?);
+            let pages = pages_at(level + 1);
+            for (i, sub_entry) in table.entries.iter_mut().enumerate() {
+                *sub_entry = PageTableEntry::new(Some(entry.page_number() + i * pages), entry.flags());
+            }
+            *entry = PageTableEntry::new(Some(table.page_number()), Flags::VALID);
+            self.page_tables.push(table);
+            // 原来的大页可能还缓存在 TLB 中
+            self.tlb.shootdown(Some(vpn..vpn + 1));
+        }
+    }
+
+    // 加入一段映射
+    // 线性映射和设备映射直接按偏移量计算物理页号；
+    // 按帧分配的映射会为每一页分配一个清零的物理页，并把这些页返回给调用者保管
+    // 中途失败时撤销这个段中已经建立的映射，不会留下指向已释放物理页的页表项
+    pub fn map(
+        &mut self,
+        segment: &Segment,
+    ) -> MemoryResult<Vec<(VirtualPageNumber, FrameTracker)>> {
+        match segment.map_type {
+            // 物理页连续，对齐允许时使用大页
+            MapType::Linear | MapType::Device => {
+                let range = segment.page_range();
+                let start_ppn = segment.iter_mapped()?.and_then(|mut pages| pages.next());
+                let mut vpn = range.start;
+                while vpn < range.end {
+                    let ppn = start_ppn.unwrap() + (vpn - range.start);
+                    let level = self.best_level(vpn, ppn, range.end);
+                    if let Err(error) = self.map_at(vpn, ppn, level, segment.flags) {
+                        self.rollback(segment, vpn);
+                        return Err(error);
+                    }
+                    vpn += pages_at(level);
+                }
+                Ok(Vec::new())
+            }
+            MapType::Framed => {
+                let range = segment.page_range();
+                let mut allocated_pairs = try_vec_with_capacity(range.end - range.start)?;
+                for vpn in range {
+                    if let Err(error) = self.map_framed(vpn, segment.flags, &mut allocated_pairs) {
+                        // 先撤销映射，之后物理页才能随 `allocated_pairs` 一起释放
+                        if !self.rollback(segment, vpn) {
+                            core::mem::forget(allocated_pairs);
+                        }
+                        return Err(error);
+                    }
+                }
+                Ok(allocated_pairs)
+            }
+            // 按需分配的段此时什么都不做，等到缺页异常时再逐页分配
+            MapType::Lazy => Ok(Vec::new()),
+        }
+    }
+
+    // 为 `vpn` 分配一个清零的物理页并建立映射，成功后记入 `allocated_pairs`
+    fn map_framed(
+        &mut self,
+        vpn: VirtualPageNumber,
+        flags: Flags,
+        allocated_pairs: &mut Vec<(VirtualPageNumber, FrameTracker)>,
+    ) -> MemoryResult<()> {
+        // 新分配的物理页可能还残留着之前的数据，必须清零
+        let mut frame = FRAME_ALLOCATOR.lock().alloc()?;
+        frame.fill(0);
+        self.map_one(vpn, frame.page_number(), flags)?;
+        allocated_pairs.push((vpn, frame));
+        Ok(())
+    }
+
+    // 撤销 `segment` 中 `end` 之前已经建立的映射，返回是否成功
+    // 这些映射都是刚刚按段内的对齐建立的，移除时不需要拆分大页，因此不会再分配物理页
+    fn rollback(&mut self, segment: &Segment, end: VirtualPageNumber) -> bool {
+        let mapped = Segment {
+            range: segment.range.start..VirtualAddress::from(end).max(segment.range.start),
+            ..segment.clone()
+        };
+        mapped.range.is_empty() || self.unmap(&mapped).is_ok()
+    }
+
+    // 移除一段映射
+    // 完全位于段内的大页直接移除，只有一部分在段内的大页先拆分
+    // 按帧分配的物理页由调用者负责释放
+    pub fn unmap(&mut self, segment: &Segment) -> MemoryResult<()> {
+        let range = segment.page_range();
+        let mut vpn = range.start;
+        while vpn < range.end {
+            match self.find_leaf(vpn) {
+                Some((entry, level))
+                    if vpn.0.is_multiple_of(pages_at(level)) && range.end - vpn >= pages_at(level) =>
+                {
+                    entry.clear();
+                    self.tlb.shootdown(Some(vpn..vpn + 1));
+                    vpn += pages_at(level);
+                }
+                Some(_) => {
+                    self.unmap_one(vpn)?;
+                    vpn += 1;
+                }
+                // 按需分配的段中，从未被访问过的页本来就没有映射
+                None if segment.map_type == MapType::Lazy => vpn += 1,
+                None => return Err("virtual address is not mapped"),
+            }
+        }
+        Ok(())
+    }
+
+    // 取消给定虚拟页号的映射，位于大页中时先拆分
+    pub fn unmap_one(&mut self, vpn: VirtualPageNumber) -> MemoryResult<()> {
+        let entry = self.split(vpn)?;
+        entry.clear();
+        self.tlb.shootdown(Some(vpn..vpn + 1));
+        Ok(())
+    }
+
+    // 修改给定虚拟页号的权限，物理页号保持不变；位于大页中时先拆分
+    // 原有的访问位和脏位保留下来，页面置换依赖它们判断交换区中的副本是否过期
+    pub fn update_flags(&mut self, vpn: VirtualPageNumber, flags: Flags) -> MemoryResult<()> {
+        let entry = self.split(vpn)?;
+        let used = entry.flags() & (Flags::ACCESSED | Flags::DIRTY);
+        entry.set_flags(flags | used | Flags::VALID);
+        self.tlb.shootdown(Some(vpn..vpn + 1));
+        Ok(())
+    }
+
+    // 清除给定虚拟页号的访问位，其余标志位保持不变
+    pub fn clear_accessed(&mut self, vpn: VirtualPageNumber) -> MemoryResult<()> {
+        let entry = self.split(vpn)?;
+        entry.set_flags(entry.flags() - Flags::ACCESSED);
+        self.tlb.shootdown(Some(vpn..vpn + 1));
+        Ok(())
+    }
+
+    // 把给定虚拟页号改为映射到另一个物理页，位于大页中时先拆分
+    pub fn remap_one(
+        &mut self,
+        vpn: VirtualPageNumber,
+        ppn: PhysicalPageNumber,
+        flags: Flags,
+    ) -> MemoryResult<()> {
+        let entry = self.split(vpn)?;
+        *entry = PageTableEntry::new(Some(ppn), flags | Flags::VALID);
+        self.tlb.shootdown(Some(vpn..vpn + 1));
+        Ok(())
+    }
+
+    // 查询虚拟页号对应的页表项
+    // 位于大页中时，返回的页表项中是这一个 4KB 页对应的物理页号
+    pub fn lookup(&self, vpn: VirtualPageNumber) -> Option<PageTableEntry> {
+        let (entry, level) = self.find_leaf(vpn)?;
+        let ppn = entry.page_number() + vpn.0 % pages_at(level);
+        Some(PageTableEntry::new(Some(ppn), entry.flags()))
+    }
+
+    // 映射给定虚拟页号的页的大小（字节），未映射时返回 `None`
+    #[allow(dead_code)]
+    pub fn page_size(&self, vpn: VirtualPageNumber) -> Option<usize> {
+        self.find_leaf(vpn)
+            .map(|(_, level)| pages_at(level) * PAGE_SIZE)
+    }
+
+    // 页表占用的物理页数
+    #[allow(dead_code)]
+    pub fn table_count(&self) -> usize {
+        self.page_tables.len()
+    }
+
+    // 将虚拟地址翻译为物理地址，未映射时返回 `None`
+    pub fn translate(&self, va: VirtualAddress) -> Option<PhysicalAddress> {
+        self.lookup(VirtualPageNumber::floor(va))
+            .map(|entry| entry.address() + va.page_offset())
+    }
+
+    // 新建某一页的映射之后，刷新它在当前 hart 上的 TLB 缓存
+    pub fn flush_local(&self, vpn: VirtualPageNumber) {
+        self.tlb.flush_local(vpn);
+    }
+
+    // 将当前的映射加载到 `satp` 寄存器
+    // 每个映射有自己的 ASID，TLB 中其他地址空间的条目不会被误用，因此通常不需要刷新 TLB
+    pub fn activate(&self) {
+        let (asid, flush) = self.tlb.activate();
+        unsafe {
+            // 将 satp 模式设为 Sv39，并写入 ASID 和根页表的物理页号
+            satp::set(satp::Mode::Sv39, asid, self.root_ppn.0);
+            // ASID 进入了新的一代，或者硬件不支持 ASID，CPU 可能仍然缓存着旧的映射
+            if flush {
+                riscv::asm::sfence_vma_all();
+            }
+        }
+    }
+}
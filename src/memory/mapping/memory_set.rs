@@ -0,0 +1,270 @@
+// 地址空间
+// 一个 [`MemorySet`] 就是一个完整的虚拟地址空间：一棵页表加上组成它的若干个 [`Segment`]。
+// 内核有自己的地址空间；以后每个用户程序也各有一个，彼此隔离。
+
+use super::mapper::Mapping;
+use super::page_fault::{AccessType, FaultKind, Privilege};
+use super::page_table_entry::Flags;
+use super::segment::{MapType, Segment};
+use crate::memory::{
+    MemoryResult,
+    address::*,
+    config::*,
+    frame::FrameTracker,
+    heap::TryVec,
+    swap::SwapSlot,
+};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+unsafe extern "C" {
+    // 由 `linker.ld` 指定的各个段的起始位置
+    fn text_start();
+    fn rodata_start();
+    fn data_start();
+    fn bss_start();
+    // 由 `entry.asm` 预留的启动栈
+    fn boot_stack();
+    fn boot_stack_top();
+}
+
+// 一个进程（或内核）所有内存空间管理的信息
+pub struct MemorySet {
+    // 维护页表和映射关系
+    pub mapping: Mapping,
+    // 组成地址空间的所有段
+    pub segments: Vec<Segment>,
+    // 所有分配的物理页面映射信息
+    pub allocated_pairs: Vec<(VirtualPageNumber, FrameTracker)>,
+    // 在交换区中有副本的页（见 `reclaim.rs`）
+    pub swap_slots: Vec<(VirtualPageNumber, SwapSlot)>,
+    // 页面置换时时钟算法的指针，指向 `allocated_pairs` 中的下标
+    pub clock_hand: usize,
+}
+
+impl MemorySet {
+    // 创建内核重映射
+    // - .text   可读、可执行
+    // - .rodata 只读
+    // - .data / .bss 可读、可写
+    // - 启动栈可读、可写，其下方的保护区域不映射
+    // - 内核结束到内存结束之间的空闲物理内存可读、可写（供帧分配器使用）
+    pub fn new_kernel() -> MemoryResult<MemorySet> {
+        let text = VirtualAddress(text_start as *const () as usize);
+        let rodata = VirtualAddress(rodata_start as *const () as usize);
+        let data = VirtualAddress(data_start as *const () as usize);
+        let bss = VirtualAddress(bss_start as *const () as usize);
+        let stack = VirtualAddress(boot_stack as *const () as usize);
+        let stack_top = VirtualAddress(boot_stack_top as *const () as usize);
+        let guard = super::boot_stack_guard_range();
+        // 建立段
+        let segments = [
+            // .text 段，r-x
+            Segment {
+                map_type: MapType::Linear,
+                range: text..rodata,
+                flags: Flags::READABLE | Flags::EXECUTABLE,
+            },
+            // .rodata 段，r--
+            Segment {
+                map_type: MapType::Linear,
+                range: rodata..data,
+                flags: Flags::READABLE,
+            },
+            // .data 段，rw-
+            Segment {
+                map_type: MapType::Linear,
+                range: data..guard.start,
+                flags: Flags::READABLE | Flags::WRITABLE,
+            },
+            // 启动栈，rw-（guard..stack 为保护区域，不映射）
+            Segment {
+                map_type: MapType::Linear,
+                range: stack..stack_top,
+                flags: Flags::READABLE | Flags::WRITABLE,
+            },
+            // .bss 段，rw-
+            Segment {
+                map_type: MapType::Linear,
+                range: bss..*KERNEL_END_ADDRESS,
+                flags: Flags::READABLE | Flags::WRITABLE,
+            },
+            // 剩余内存空间，rw-
+            Segment {
+                map_type: MapType::Linear,
+                range: *KERNEL_END_ADDRESS..VirtualAddress::from(*MEMORY_END_ADDRESS),
+                flags: Flags::READABLE | Flags::WRITABLE,
+            },
+        ];
+        let mut memory_set = MemorySet {
+            mapping: Mapping::new()?,
+            segments: Vec::new(),
+            allocated_pairs: Vec::new(),
+            swap_slots: Vec::new(),
+            clock_hand: 0,
+        };
+        // 每个段都添加映射
+        for segment in segments {
+            memory_set.add_segment(segment, None)?;
+        }
+        // 内核初始化时映射的设备寄存器
+        for segment in super::KERNEL_DEVICES.lock().iter() {
+            memory_set.add_segment(segment.clone(), None)?;
+        }
+        // 所有的内核栈
+        super::kernel_stack::share_stacks(&mut memory_set.mapping);
+        Ok(memory_set)
+    }
+
+    // 添加一个 [`Segment`] 的内存映射，并可以在其中填入初始数据
+    // 初始数据从段的起始地址开始写入，长度不能超过段的大小
+    pub fn add_segment(&mut self, segment: Segment, init_data: Option<&[u8]>) -> MemoryResult<()> {
+        // 检测段是否重合
+        if self.overlap_with(segment.page_range()) {
+            return Err("segment overlaps with existing segments");
+        }
+        if let Some(data) = init_data {
+            if segment.map_type == MapType::Device {
+                return Err("cannot copy data into a device segment");
+            }
+            if segment.map_type == MapType::Lazy {
+                return Err("cannot copy data into a lazy segment");
+            }
+            if data.len() > segment.range.end - segment.range.start {
+                return Err("init data is larger than the segment");
+            }
+        }
+        // 先预留记录段和物理页的空间，映射之后就不会再因为内存不足而失败
+        self.segments.try_reserve_additional(1)?;
+        if segment.map_type == MapType::Framed {
+            let range = segment.page_range();
+            self.allocated_pairs
+                .try_reserve_additional(range.end - range.start)?;
+        }
+        // 映射，`map` 失败时已经撤销了这个段中的映射
+        let allocated_pairs = self.mapping.map(&segment)?;
+        if let Some(data) = init_data
+            && let Err(error) = self.copy_data(segment.range.start, data)
+        {
+            // 先撤销映射再释放物理页；撤销失败时宁可泄漏，也不能释放仍被页表引用的页
+            if self.mapping.unmap(&segment).is_err() {
+                core::mem::forget(allocated_pairs);
+            }
+            return Err(error);
+        }
+        self.allocated_pairs.extend(allocated_pairs);
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    // 移除一个 [`Segment`] 的内存映射
+    // `segment` 必须已经映射，按帧分配的物理页会随之释放
+    pub fn remove_segment(&mut self, segment: &Segment) -> MemoryResult<()> {
+        // 找到对应的段
+        let index = self
+            .segments
+            .iter()
+            .position(|s| s == segment)
+            .ok_or("segment to remove does not exist")?;
+        // 移除映射
+        self.mapping.unmap(segment)?;
+        // 释放物理页（drop FrameTracker 即可）
+        let page_range = segment.page_range();
+        self.allocated_pairs
+            .retain(|(vpn, _)| !page_range.contains(vpn));
+        self.swap_slots
+            .retain(|(vpn, _)| !page_range.contains(vpn));
+        // 移除段
+        self.segments.remove(index);
+        Ok(())
+    }
+
+    // 向地址空间中已经映射的虚拟地址写入数据
+    // 逐页查询页表得到物理地址，再通过内核的线性映射写入，因此该地址空间不需要处于激活状态
+    pub fn copy_data(&self, va: VirtualAddress, data: &[u8]) -> MemoryResult<()> {
+        let mut copied = 0;
+        while copied < data.len() {
+            let current = va + copied;
+            let pa = self
+                .mapping
+                .translate(current)
+                .ok_or("virtual address is not mapped")?;
+            // 本页中剩余可以写入的字节数
+            let length = (PAGE_SIZE - current.page_offset()).min(data.len() - copied);
+            // 已经映射的 Framed 页，物理页位于线性映射之中
+            let destination: &mut [u8; PAGE_SIZE] =
+                unsafe { PhysicalPageNumber::floor(pa).deref_kernel() };
+            destination[pa.page_offset()..pa.page_offset() + length]
+                .copy_from_slice(&data[copied..copied + length]);
+            copied += length;
+        }
+        Ok(())
+    }
+
+    // 处理该地址空间中的缺页异常，返回这次缺页的种类
+    // 只有按需分配的段中尚未分配的页才能在这里补上，其余情况都是真正的非法访问
+    pub fn handle_page_fault(
+        &mut self,
+        va: VirtualAddress,
+        access: AccessType,
+        privilege: Privilege,
+    ) -> MemoryResult<FaultKind> {
+        let vpn = VirtualPageNumber::floor(va);
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| segment.page_range().contains(&vpn))
+            .ok_or("address is not in any segment")?;
+        if !access.permitted_by(segment.flags, privilege) {
+            return Err("access violates segment permissions");
+        }
+        let (flags, map_type) = (segment.flags, segment.map_type);
+        if let Some(entry) = self.mapping.lookup(vpn) {
+            // 段允许写入而页表项没有写权限：写时复制的页
+            if access == AccessType::Store && !entry.flags().contains(Flags::WRITABLE) {
+                return self.handle_cow_fault(vpn, flags).map(|_| FaultKind::Minor);
+            }
+            // 硬件不自动更新访问位和脏位时，访问这样的页也会触发缺页异常，由这里补上
+            let mut used = Flags::ACCESSED;
+            if access == AccessType::Store {
+                used |= Flags::DIRTY;
+            }
+            if !entry.flags().contains(used) {
+                self.mapping.update_flags(vpn, entry.flags() | used)?;
+                return Ok(FaultKind::Minor);
+            }
+            // 页已经存在且权限允许，说明 TLB 中缓存的是旧的映射，刷新后重新执行即可
+            self.mapping.flush_local(vpn);
+            return Ok(FaultKind::Minor);
+        }
+        // 被换出到交换区的页
+        if self.swap_in(vpn, flags)? {
+            return Ok(FaultKind::Major);
+        }
+        if map_type != MapType::Lazy {
+            return Err("page is missing in an eagerly mapped segment");
+        }
+        // 分配一个清零的物理页并映射，物理页不够时先换出一些页
+        self.allocated_pairs.try_reserve_additional(1)?;
+        let mut frame = self.alloc_frame()?;
+        frame.fill(0);
+        self.mapping
+            .map_one(vpn, frame.page_number(), flags)?;
+        self.mapping.flush_local(vpn);
+        self.allocated_pairs.push((vpn, frame));
+        Ok(FaultKind::Minor)
+    }
+
+    // 检测一段内存区域和已有的是否存在重叠区域
+    pub fn overlap_with(&self, range: Range<VirtualPageNumber>) -> bool {
+        self.segments.iter().any(|segment| {
+            let other = segment.page_range();
+            range.start < other.end && other.start < range.end
+        })
+    }
+
+    // 替换 `satp` 以激活页表，并刷新 TLB
+    pub fn activate(&self) {
+        self.mapping.activate()
+    }
+}
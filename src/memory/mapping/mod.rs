@@ -0,0 +1,103 @@
+// 内存映射
+// 负责 Sv39 页表的构建，以及地址空间（[`MemorySet`]）的管理。
+//
+// 启动时按段重新映射内核（见 [`MemorySet::new_kernel`]）：
+// .text 可读可执行、.rodata 只读、.data / .bss 可读可写。
+// 所有区域都使用线性映射（虚拟地址 = 物理地址 + KERNEL_MAP_OFFSET），
+// 与 `entry.asm` 中启动页表的高半部分一致，因此切换页表前后代码都能继续运行。
+// 启动栈下方的保护区域不映射，栈溢出时会触发缺页异常（见 [`kernel_stack`]）。
+
+mod cow;
+mod kernel_stack;
+mod mapper;
+mod memory_set;
+mod page_fault;
+mod page_table;
+mod page_table_entry;
+mod reclaim;
+mod segment;
+mod tlb;
+mod user_mapping;
+
+#[allow(unused_imports)]
+pub use kernel_stack::{KernelStack, boot_stack_guard_range, is_guard_page};
+#[allow(unused_imports)]
+pub use mapper::Mapping;
+pub use memory_set::MemorySet;
+#[allow(unused_imports)]
+pub use page_fault::{
+    AccessType, FaultKind, PageFaultStats, Privilege, handle_page_fault, stats,
+};
+#[allow(unused_imports)]
+pub use page_table::{PageTable, PageTableTracker};
+#[allow(unused_imports)]
+pub use page_table_entry::{Flags, PageTableEntry};
+pub use segment::{MapType, Segment};
+#[allow(unused_imports)]
+pub use tlb::asid_count;
+pub use user_mapping::MmapError;
+
+use crate::memory::{MemoryResult, address::VirtualAddress, heap::TryVec};
+use alloc::{sync::Arc, vec::Vec};
+use core::ops::Range;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+lazy_static! {
+    // 内核的地址空间
+    // 必须一直保存着：一旦被 drop，页表所在的物理页会被回收，CPU 就会读到错误的页表
+    pub static ref KERNEL_MEMORY_SET: Arc<Mutex<MemorySet>> =
+        Arc::new(Mutex::new(MemorySet::new_kernel().unwrap()));
+}
+
+// 内核映射的设备寄存器（例如 PLIC）
+// 中断处理函数在任何地址空间中都可能访问它们，因此之后由 [`MemorySet::new_kernel`] 创建的地址空间也都包含这些段
+static KERNEL_DEVICES: Mutex<Vec<Segment>> = Mutex::new(Vec::new());
+
+// 当前正在使用（写在 `satp` 中）的地址空间
+// 缺页异常发生时，就在这个地址空间中查找出错的地址
+static CURRENT_MEMORY_SET: Mutex<Option<Arc<Mutex<MemorySet>>>> = Mutex::new(None);
+
+// 按段重新映射内核，并开启 Sv39 分页
+pub fn init() {
+    tlb::init();
+    activate(KERNEL_MEMORY_SET.clone());
+    println!("mod mapping initialized");
+}
+
+// 把设备寄存器映射到内核的地址空间，之后新建的地址空间也会映射这个段
+// 已经存在的其他地址空间不会补上，因此设备应当在创建其他地址空间之前初始化
+pub fn map_kernel_device(segment: Segment) -> MemoryResult<()> {
+    if segment.map_type != MapType::Device {
+        return Err("not a device segment");
+    }
+    // 先映射到内核的地址空间：第一次访问 KERNEL_MEMORY_SET 时会创建它，其中也要获取 KERNEL_DEVICES 的锁
+    KERNEL_MEMORY_SET.lock().add_segment(segment.clone(), None)?;
+    let mut devices = KERNEL_DEVICES.lock();
+    devices.try_reserve_additional(1)?;
+    devices.push(segment);
+    Ok(())
+}
+
+// 在当前地址空间中，用户态能否对 `range` 进行 `access` 访问
+// 地址空间的锁正被持有时无法检查，按不允许处理
+pub fn user_can_access(range: Range<VirtualAddress>, access: AccessType) -> bool {
+    let memory_set = current();
+    memory_set
+        .try_lock()
+        .is_some_and(|memory_set| memory_set.user_can_access(range, access))
+}
+
+// 切换到给定的地址空间，并把它记录为当前地址空间
+pub fn activate(memory_set: Arc<Mutex<MemorySet>>) {
+    memory_set.lock().activate();
+    *CURRENT_MEMORY_SET.lock() = Some(memory_set);
+}
+
+// 获取当前地址空间
+pub fn current() -> Arc<Mutex<MemorySet>> {
+    CURRENT_MEMORY_SET
+        .lock()
+        .clone()
+        .expect("no address space is active")
+}
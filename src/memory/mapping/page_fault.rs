@@ -0,0 +1,112 @@
+// 缺页异常处理（按需分配）
+// 中断处理程序收到 Load / Store / InstructionPageFault 后交给这里：
+// 在当前地址空间中查找出错地址所在的段，如果是按需分配的段，就为它分配并映射一个清零的物理页。
+// 需要从交换区读回数据的缺页记为 major fault，其余成功处理的记为 minor fault。
+
+use super::memory_set::MemorySet;
+use super::page_table_entry::Flags;
+use crate::memory::{MemoryResult, address::VirtualAddress};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+// 触发缺页异常的访问类型
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AccessType {
+    // 读数据（LoadPageFault）
+    Load,
+    // 写数据（StorePageFault）
+    Store,
+    // 取指令（InstructionPageFault）
+    Execute,
+}
+
+// 触发缺页异常时所处的特权级
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Privilege {
+    // 用户态，只能访问带 USER 标志的页
+    User,
+    // 内核态，`sum` 为 sstatus 的 SUM 位，打开时才能读写带 USER 标志的页
+    Supervisor { sum: bool },
+}
+
+// 成功处理的缺页异常的种类
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FaultKind {
+    // 不需要读磁盘：按需分配、写时复制、补上访问位和脏位或者刷新 TLB
+    Minor,
+    // 从交换区换入
+    Major,
+}
+
+impl AccessType {
+    // 段的权限是否允许在 `privilege` 下进行这种访问
+    // 和硬件的检查保持一致：不允许时即使页已经存在，重新执行也只会再次触发缺页异常
+    pub fn permitted_by(self, flags: Flags, privilege: Privilege) -> bool {
+        let user = flags.contains(Flags::USER);
+        let privileged = match privilege {
+            Privilege::User => user,
+            // 内核态无论如何都不能执行用户页
+            Privilege::Supervisor { sum } => !user || (sum && self != AccessType::Execute),
+        };
+        privileged
+            && match self {
+                AccessType::Load => flags.contains(Flags::READABLE),
+                AccessType::Store => flags.contains(Flags::WRITABLE),
+                AccessType::Execute => flags.contains(Flags::EXECUTABLE),
+            }
+    }
+}
+
+// 成功补上物理页的缺页次数（minor fault：不需要读磁盘）
+static MINOR_FAULTS: AtomicUsize = AtomicUsize::new(0);
+// 从交换区换入的缺页次数（major fault：需要读磁盘）
+static MAJOR_FAULTS: AtomicUsize = AtomicUsize::new(0);
+// 无法处理的缺页次数（真正的非法访问）
+static INVALID_FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+// 缺页统计
+#[derive(Debug, Copy, Clone)]
+pub struct PageFaultStats {
+    pub minor: usize,
+    pub major: usize,
+    pub invalid: usize,
+}
+
+// 获取缺页统计
+pub fn stats() -> PageFaultStats {
+    PageFaultStats {
+        minor: MINOR_FAULTS.load(Ordering::Relaxed),
+        major: MAJOR_FAULTS.load(Ordering::Relaxed),
+        invalid: INVALID_FAULTS.load(Ordering::Relaxed),
+    }
+}
+
+// 在当前地址空间中处理一次缺页异常
+// 返回 `Err` 表示这是一次非法访问，由调用者决定 panic 还是结束出错的程序
+pub fn handle_page_fault(
+    va: VirtualAddress,
+    access: AccessType,
+    privilege: Privilege,
+) -> MemoryResult<FaultKind> {
+    let result = try_handle(&super::current(), va, access, privilege);
+    match result {
+        Ok(FaultKind::Minor) => MINOR_FAULTS.fetch_add(1, Ordering::Relaxed),
+        Ok(FaultKind::Major) => MAJOR_FAULTS.fetch_add(1, Ordering::Relaxed),
+        Err(_) => INVALID_FAULTS.fetch_add(1, Ordering::Relaxed),
+    };
+    result
+}
+
+fn try_handle(
+    memory_set: &Arc<Mutex<MemorySet>>,
+    va: VirtualAddress,
+    access: AccessType,
+    privilege: Privilege,
+) -> MemoryResult<FaultKind> {
+    // 如果出错时正持有这个地址空间的锁，再去等待它只会死锁
+    memory_set
+        .try_lock()
+        .ok_or("page fault while the address space is locked")?
+        .handle_page_fault(va, access, privilege)
+}
@@ -0,0 +1,126 @@
+// 页表项
+// Sv39 的页表项占 8 字节，结构如下：
+// | 63..54 保留 | 53..10 物理页号 PPN | 9..8 RSW | 7 D | 6 A | 5 G | 4 U | 3 X | 2 W | 1 R | 0 V |
+
+use super::page_table::PageTable;
+use crate::memory::address::*;
+use bitflags::bitflags;
+use core::fmt::{self, Debug, Formatter};
+
+// 物理页号在页表项中的位置
+const PAGE_NUMBER_SHIFT: usize = 10;
+// 物理页号的掩码（44 位）
+const PAGE_NUMBER_MASK: usize = (1 << 44) - 1;
+// 标志位的掩码（低 8 位）
+const FLAG_MASK: usize = 0xff;
+
+// Sv39 结构的页表项
+#[derive(Copy, Clone, Default)]
+pub struct PageTableEntry(usize);
+
+impl PageTableEntry {
+    // 将相应页号和标志写入一个页表项
+    pub fn new(page_number: Option<PhysicalPageNumber>, flags: Flags) -> Self {
+        Self(
+            (page_number.map_or(0, usize::from) & PAGE_NUMBER_MASK) << PAGE_NUMBER_SHIFT
+                | flags.bits() as usize,
+        )
+    }
+
+    // 设置物理页号，同时根据 `ppn` 是否为 `None` 设置 VALID 位
+    #[allow(dead_code)]
+    pub fn update_page_number(&mut self, ppn: Option<PhysicalPageNumber>) {
+        let flags = self.flags();
+        let flags = if ppn.is_some() {
+            flags | Flags::VALID
+        } else {
+            flags - Flags::VALID
+        };
+        *self = Self::new(ppn, flags);
+    }
+
+    // 清除页表项
+    pub fn clear(&mut self) {
+        self.0 = 0;
+    }
+
+    // 获取页号
+    pub fn page_number(&self) -> PhysicalPageNumber {
+        PhysicalPageNumber((self.0 >> PAGE_NUMBER_SHIFT) & PAGE_NUMBER_MASK)
+    }
+
+    // 获取地址
+    pub fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::from(self.page_number())
+    }
+
+    // 获取标志位
+    pub fn flags(&self) -> Flags {
+        Flags::from_bits_truncate((self.0 & FLAG_MASK) as u8)
+    }
+
+    // 只替换标志位，物理页号保持不变
+    pub fn set_flags(&mut self, flags: Flags) {
+        self.0 = (self.0 & !FLAG_MASK) | flags.bits() as usize;
+    }
+
+    // 是否为空（可能非空也非 Valid）
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    // 是否有效
+    pub fn is_valid(&self) -> bool {
+        self.flags().contains(Flags::VALID)
+    }
+
+    // 是否指向下一级页表
+    // 有效且 R / W / X 均为 0 的页表项指向下一级页表，否则就是叶子节点
+    pub fn has_next_level(&self) -> bool {
+        self.is_valid()
+            && !self
+                .flags()
+                .intersects(Flags::READABLE | Flags::WRITABLE | Flags::EXECUTABLE)
+    }
+
+    // 获取下一级页表
+    // 调用者需要保证 [`has_next_level`](Self::has_next_level) 为真
+    pub fn get_next_table(&self) -> &'static mut PageTable {
+        // 非叶子页表项指向的是 `Mapping` 分配的页表
+        unsafe { self.address().deref_kernel() }
+    }
+}
+
+impl Debug for PageTableEntry {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("PageTableEntry")
+            .field("value", &self.0)
+            .field("page_number", &self.page_number())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+bitflags! {
+    // 页表项中的 8 个标志位
+    #[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+    pub struct Flags: u8 {
+        // 有效位
+        const VALID =       1 << 0;
+        // 可读位
+        const READABLE =    1 << 1;
+        // 可写位
+        const WRITABLE =    1 << 2;
+        // 可执行位
+        const EXECUTABLE =  1 << 3;
+        // 用户位
+        const USER =        1 << 4;
+        // 全局位，我们不会使用
+        const GLOBAL =      1 << 5;
+        // 已使用位，用于替换算法
+        const ACCESSED =    1 << 6;
+        // 已修改位，用于替换算法
+        const DIRTY =       1 << 7;
+    }
+}
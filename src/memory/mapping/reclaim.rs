@@ -0,0 +1,117 @@
+// 页面置换
+// 物理页不够时，从地址空间中挑出最近没有被访问的用户页写入交换区，释放它们占用的帧。
+// 挑选使用时钟（第二次机会）算法：时钟指针在 `allocated_pairs` 上循环移动，
+// 遇到访问位（ACCESSED）为 1 的页就清除访问位、给它第二次机会，遇到访问位为 0 的页才换出。
+//
+// 被换出的页从页表中移除，槽位记在 `swap_slots` 中，再次访问时在缺页异常中换入。
+// 换入之后槽位仍然保留着这一页的副本：如果之后没有被写过（DIRTY 为 0），再次换出时不需要重新写入。
+// 因此修改权限时要保留脏位；页被暂时移除映射、脏位随页表项一起丢失时，过期的副本也要一起丢弃。
+// 仍在写时复制共享的页、内核的段以及暂时没有映射的页不会被换出。
+//
+// 换出不会注册为堆的 OOM 回收函数：换出的过程要获取帧分配器、交换区等的锁，还要分配堆内存，
+// 而堆分配失败时调用者可能正持有这些锁（例如帧分配器在回收物理页时扩展空闲列表），回收函数中再去获取只会死锁。
+
+use super::memory_set::MemorySet;
+use super::page_table_entry::Flags;
+use crate::memory::{
+    MemoryResult,
+    address::*,
+    frame::{FRAME_ALLOCATOR, FrameTracker},
+    heap::TryVec,
+    swap::SwapSlot,
+};
+
+// 分配物理页失败时，一次换出的页数
+const SWAP_BATCH: usize = 8;
+
+impl MemorySet {
+    // 用时钟算法换出最多 `count` 个页，返回实际换出的页数
+    pub fn swap_out(&mut self, count: usize) -> usize {
+        let mut evicted = 0;
+        // 每一页最多经过两次：第一次清除访问位，第二次换出
+        let mut steps = 2 * self.allocated_pairs.len();
+        while evicted < count && steps > 0 && !self.allocated_pairs.is_empty() {
+            steps -= 1;
+            if self.clock_hand >= self.allocated_pairs.len() {
+                self.clock_hand = 0;
+            }
+            match self.try_evict(self.clock_hand) {
+                // 换出的页已经从 `allocated_pairs` 中移除，指针不用移动
+                Ok(true) => evicted += 1,
+                Ok(false) => self.clock_hand += 1,
+                // 交换区已满或者写入失败
+                Err(_) => break,
+            }
+        }
+        evicted
+    }
+
+    // 检查时钟指针指向的页，可以换出时换出并返回 `true`
+    fn try_evict(&mut self, index: usize) -> MemoryResult<bool> {
+        let (vpn, frame) = &self.allocated_pairs[index];
+        let vpn = *vpn;
+        let user = self.segments.iter().any(|segment| {
+            segment.page_range().contains(&vpn) && segment.flags.contains(Flags::USER)
+        });
+        if !user || frame.ref_count() > 1 {
+            return Ok(false);
+        }
+        let Some(entry) = self.mapping.lookup(vpn) else {
+            return Ok(false);
+        };
+        let flags = entry.flags();
+        if flags.contains(Flags::ACCESSED) {
+            self.mapping.clear_accessed(vpn)?;
+            return Ok(false);
+        }
+        match self.swap_slots.iter().find(|(v, _)| *v == vpn) {
+            // 交换区中的副本仍然是最新的
+            Some(_) if !flags.contains(Flags::DIRTY) => {}
+            Some((_, slot)) => slot.write(frame)?,
+            None => {
+                self.swap_slots.try_reserve_additional(1)?;
+                let slot = SwapSlot::alloc()?;
+                slot.write(frame)?;
+                self.swap_slots.push((vpn, slot));
+            }
+        }
+        self.mapping.unmap_one(vpn)?;
+        // 帧随着 FrameTracker 一起释放
+        self.allocated_pairs.remove(index);
+        Ok(true)
+    }
+
+    // 如果这一页在交换区中，就把它换入并按 `flags` 映射，返回是否换入
+    pub(super) fn swap_in(&mut self, vpn: VirtualPageNumber, flags: Flags) -> MemoryResult<bool> {
+        if self.allocated_pairs.iter().any(|(v, _)| *v == vpn) {
+            return Ok(false);
+        }
+        let Some(index) = self.swap_slots.iter().position(|(v, _)| *v == vpn) else {
+            return Ok(false);
+        };
+        self.allocated_pairs.try_reserve_additional(1)?;
+        let mut frame = self.alloc_frame()?;
+        self.swap_slots[index].1.read(&mut frame)?;
+        self.mapping.map_one(vpn, frame.page_number(), flags)?;
+        self.mapping.flush_local(vpn);
+        self.allocated_pairs.push((vpn, frame));
+        Ok(true)
+    }
+
+    // 这一页被写过、交换区中的副本已经过期时，丢弃副本
+    pub(super) fn discard_stale_copy(&mut self, vpn: VirtualPageNumber, flags: Flags) {
+        if flags.contains(Flags::DIRTY) {
+            self.swap_slots.retain(|(v, _)| *v != vpn);
+        }
+    }
+
+    // 分配一个物理页，没有空闲的物理页时先换出这个地址空间中的一些页
+    pub(super) fn alloc_frame(&mut self) -> MemoryResult<FrameTracker> {
+        let frame = FRAME_ALLOCATOR.lock().alloc();
+        match frame {
+            Ok(frame) => Ok(frame),
+            Err(_) if self.swap_out(SWAP_BATCH) > 0 => FRAME_ALLOCATOR.lock().alloc(),
+            Err(error) => Err(error),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// 映射的一个段
+// 地址空间由若干个段组成，每个段是一段连续的虚拟地址区间，拥有相同的映射方式和权限。
+
+use super::page_table_entry::Flags;
+use crate::memory::{MemoryResult, address::*};
+use core::ops::Range;
+
+// 映射的类型
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MapType {
+    // 线性映射，虚拟地址 = 物理地址 + KERNEL_MAP_OFFSET，用于内核镜像和空闲物理内存
+    Linear,
+    // 按帧分配映射，每个虚拟页都对应一个新分配的物理页，物理页之间不一定连续
+    Framed,
+    // 设备（MMIO）映射，偏移方式与线性映射相同，但对应的是设备寄存器而不是内存，
+    // 因此不能用来存放初始数据
+    Device,
+    // 按需分配映射，添加时只预留虚拟地址区间，不分配物理页；
+    // 第一次访问某一页触发缺页异常时，才为它分配一个清零的物理页（用于堆、栈、匿名映射等）
+    Lazy,
+}
+
+// 一个映射片段
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Segment {
+    // 映射类型
+    pub map_type: MapType,
+    // 所映射的虚拟地址
+    pub range: Range<VirtualAddress>,
+    // 权限标志
+    pub flags: Flags,
+}
+
+impl Segment {
+    // 遍历对应的物理页号（如果可以直接得到的话）
+    // 按帧分配和按需分配的段在映射之前无法知道物理页号，返回 `None`；
+    // 线性映射和设备映射的段不在线性映射区域中时返回错误
+    pub fn iter_mapped(&self) -> MemoryResult<Option<impl Iterator<Item = PhysicalPageNumber>>> {
+        match self.map_type {
+            MapType::Linear | MapType::Device => {
+                let range = self.page_range();
+                let start = PhysicalPageNumber::try_from(range.start)?;
+                Ok(Some(start..start + (range.end - range.start)))
+            }
+            MapType::Framed | MapType::Lazy => Ok(None),
+        }
+    }
+
+    // 将地址相应地上下取整，获得虚拟页号区间
+    pub fn page_range(&self) -> Range<VirtualPageNumber> {
+        VirtualPageNumber::floor(self.range.start)..VirtualPageNumber::ceil(self.range.end)
+    }
+}
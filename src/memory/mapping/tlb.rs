@@ -0,0 +1,179 @@
+// TLB 管理：地址空间标识符（ASID）的分配，以及跨 hart 的 TLB 刷新
+// satp 中的 ASID 字段给 TLB 中的条目打上所属地址空间的标记，切换地址空间时就不必清空整个 TLB。
+// ASID 的位数由硬件决定（Sv39 下最多 16 位），不一定够每个地址空间独占一个，因此按“代”分配：
+// 同一代中 ASID 只递增分配、从不回收，地址空间销毁后它的 ASID 也不会被别人拿到；
+// 用完之后进入下一代，所有 hart 清空 TLB，之前分配的 ASID 全部作废，各个地址空间下一次激活时重新分配。
+//
+// 修改或取消已有的映射后，除了刷新本 hart 的 TLB，还要通过 SBI 让其他可能缓存了
+// 这个地址空间的 hart 也刷新（TLB shootdown）。新建映射只需刷新本 hart：
+// 其他 hart 即使缓存了无效的条目，也只会触发一次缺页异常，在那里再刷新即可。
+
+use crate::interrupt::{MAX_HARTS, current_hart};
+use crate::memory::address::*;
+use crate::sbi;
+use core::arch::asm;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use riscv::register::satp;
+use spin::Mutex;
+
+// satp 中 ASID 字段的位数上限
+const ASID_BITS: usize = 16;
+// 超过这么多页时，直接刷新整个地址空间，而不是逐页刷新
+const FLUSH_PAGE_LIMIT: usize = 64;
+
+// 按代分配 ASID
+struct AsidAllocator {
+    // 当前的代数，从 1 开始
+    generation: usize,
+    // 本代中下一个可以分配的 ASID
+    next: usize,
+    // 硬件支持的最大 ASID，为 0 表示不支持 ASID
+    max: usize,
+}
+
+static ASID_ALLOCATOR: Mutex<AsidAllocator> = Mutex::new(AsidAllocator {
+    generation: 1,
+    next: 1,
+    max: 0,
+});
+
+// 下一次切换地址空间时需要清空整个 TLB 的 hart，第 i 位对应 hart i
+// 初始时全部置位，清掉启动页表留下的条目
+static PENDING_FLUSH: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// 检测硬件支持的 ASID 位数：向 satp 的 ASID 字段写入全 1，再读回实际保留的位
+// 必须在开启分页之后、第一次切换地址空间之前调用
+pub fn init() {
+    let original = satp::read();
+    let max = unsafe {
+        satp::set(satp::Mode::Sv39, (1 << ASID_BITS) - 1, original.ppn());
+        let max = satp::read().asid();
+        satp::set(satp::Mode::Sv39, original.asid(), original.ppn());
+        max
+    };
+    ASID_ALLOCATOR.lock().max = max;
+    println!("mod tlb initialized (asid bits: {})", max.count_ones());
+}
+
+// 硬件支持的 ASID 个数（含保留的 0）
+#[allow(dead_code)]
+pub fn asid_count() -> usize {
+    ASID_ALLOCATOR.lock().max + 1
+}
+
+// 按 hart 记录的位图放在一个 usize 中
+const _: () = assert!(MAX_HARTS <= usize::BITS as usize);
+
+// 一个地址空间的 ASID，以及可能缓存了它的 hart
+pub struct TlbContext {
+    // 高位为分配时的代数，低 ASID_BITS 位为 ASID；0 表示还没有分配
+    id: AtomicUsize,
+    // 激活过这个地址空间的 hart
+    // 切换走之后，TLB 中仍然可能留着带这个 ASID 的条目，因此不会清除
+    harts: AtomicUsize,
+}
+
+impl TlbContext {
+    pub const fn new() -> Self {
+        Self {
+            id: AtomicUsize::new(0),
+            harts: AtomicUsize::new(0),
+        }
+    }
+
+    // 在当前 hart 上激活这个地址空间
+    // 返回要写入 satp 的 ASID，以及是否需要清空整个 TLB
+    pub fn activate(&self) -> (usize, bool) {
+        let hart = 1 << current_hart();
+        let asid = {
+            let mut allocator = ASID_ALLOCATOR.lock();
+            let id = self.id.load(Ordering::Relaxed);
+            if allocator.max == 0 {
+                // 不支持 ASID，所有地址空间共用 0，每次切换都要清空 TLB
+                PENDING_FLUSH.fetch_or(hart, Ordering::Relaxed);
+                self.id
+                    .store(allocator.generation << ASID_BITS, Ordering::Relaxed);
+                0
+            } else if id >> ASID_BITS == allocator.generation {
+                id & ((1 << ASID_BITS) - 1)
+            } else {
+                if allocator.next > allocator.max {
+                    // 本代的 ASID 已经用完，进入下一代，所有 hart 都要清空 TLB
+                    allocator.generation += 1;
+                    allocator.next = 1;
+                    PENDING_FLUSH.store(usize::MAX, Ordering::Relaxed);
+                }
+                let asid = allocator.next;
+                allocator.next += 1;
+                self.id
+                    .store(allocator.generation << ASID_BITS | asid, Ordering::Relaxed);
+                asid
+            }
+        };
+        self.harts.fetch_or(hart, Ordering::Relaxed);
+        let flush = PENDING_FLUSH.fetch_and(!hart, Ordering::Relaxed) & hart != 0;
+        (asid, flush)
+    }
+
+    // 最近一次写入 satp 的 ASID；还没有激活过时，没有 hart 缓存着这个地址空间，返回 `None`
+    // ASID 过期之后仍然返回它：进入下一代时其他 hart 要等到下一次切换地址空间才清空 TLB，
+    // 在此之前可能还在用旧的 ASID 运行这个地址空间。这个 ASID 如果已经分给了别的地址空间，
+    // 只会多刷新一些条目，不影响正确性
+    fn asid(&self) -> Option<usize> {
+        let id = self.id.load(Ordering::Relaxed);
+        (id != 0).then_some(id & ((1 << ASID_BITS) - 1))
+    }
+
+    // 只刷新当前 hart 上某一页的 TLB 缓存，用于新建映射之后
+    pub fn flush_local(&self, vpn: VirtualPageNumber) {
+        if let Some(asid) = self.asid() {
+            local_flush(asid, Some(vpn..vpn + 1));
+        }
+    }
+
+    // 在所有可能缓存了这个地址空间的 hart 上刷新 `pages` 中的页，`None` 表示整个地址空间
+    // 对于大页，刷新其中任意一页就会清掉整个大页的条目
+    pub fn shootdown(&self, pages: Option<Range<VirtualPageNumber>>) {
+        let Some(asid) = self.asid() else {
+            return;
+        };
+        let pages = pages.filter(|pages| pages.end - pages.start <= FLUSH_PAGE_LIMIT);
+        local_flush(asid, pages.clone());
+        let remote = self.harts.load(Ordering::Relaxed) & !(1 << current_hart());
+        if remote != 0 {
+            let (start, size) = match pages {
+                Some(pages) => (
+                    VirtualAddress::from(pages.start).0,
+                    VirtualAddress::from(pages.end) - VirtualAddress::from(pages.start),
+                ),
+                None => (0, usize::MAX),
+            };
+            sbi::remote_sfence_vma_asid(&remote, start, size, asid);
+        }
+    }
+}
+
+// 在所有 hart 上刷新所有地址空间中 `pages` 的条目
+// 用于所有地址空间共用的映射（例如内核栈），这样的映射不属于某一个 ASID
+pub fn shootdown_all(pages: Range<VirtualPageNumber>) {
+    let start = VirtualAddress::from(pages.start).0;
+    let size = VirtualAddress::from(pages.end).0 - start;
+    for vpn in pages {
+        unsafe { asm!("sfence.vma {}, zero", in(reg) VirtualAddress::from(vpn).0) };
+    }
+    let remote = ((1 << MAX_HARTS) - 1) & !(1 << current_hart());
+    sbi::remote_sfence_vma(&remote, start, size);
+}
+
+// 在当前 hart 上刷新带有 `asid` 标记的条目
+fn local_flush(asid: usize, pages: Option<Range<VirtualPageNumber>>) {
+    match pages {
+        Some(pages) => {
+            for vpn in pages {
+                unsafe { riscv::asm::sfence_vma(asid, VirtualAddress::from(vpn).0) };
+            }
+        }
+        None => unsafe { asm!("sfence.vma zero, {}", in(reg) asid) },
+    }
+}
@@ -0,0 +1,278 @@
+// 用户地址空间中的匿名映射
+// 为 `mmap` / `munmap` / `mprotect` 系统调用提供支持。
+// 匿名映射都是按需分配的段（[`MapType::Lazy`]），第一次访问时才分配清零的物理页。
+// 只修改一个段中的一部分时，先在区间的两端把段切开；修改之后，相邻且属性相同的段会被重新合并。
+
+use super::memory_set::MemorySet;
+use super::page_fault::{AccessType, Privilege};
+use super::page_table_entry::Flags;
+use super::segment::{MapType, Segment};
+use crate::memory::{MemoryResult, address::*, config::*, heap::TryVec};
+use core::ops::Range;
+
+// [`MemorySet::mmap`] 失败的原因，系统调用据此返回不同的错误码
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MmapError {
+    // 参数不合法：长度为 0 或溢出、地址未对齐、区间超出用户地址空间或与内核的段重叠
+    Invalid(&'static str),
+    // 内存不足，或者用户地址空间中找不到足够大的空闲区域
+    NoMemory(&'static str),
+}
+
+impl MemorySet {
+    // 映射一段匿名内存，返回映射的起始地址
+    // - `fixed` 为真时必须映射在 `hint` 处，区间内原有的映射会被移除
+    // - 否则 `hint` 只是建议的位置，不可用时从 USER_MMAP_BASE 开始寻找一段空闲区域
+    // `flags` 中不需要包含 `USER`
+    // 失败时地址空间保持不变，`fixed` 时原有的映射也不会被移除
+    pub fn mmap(
+        &mut self,
+        hint: VirtualAddress,
+        length: usize,
+        flags: Flags,
+        fixed: bool,
+    ) -> Result<VirtualAddress, MmapError> {
+        let size = page_aligned_length(length).map_err(MmapError::Invalid)?;
+        let start = if fixed {
+            let range = user_range(hint, size).map_err(MmapError::Invalid)?;
+            if self.overlaps_kernel(&range) {
+                return Err(MmapError::Invalid("range overlaps with a kernel segment"));
+            }
+            // 区间两端各可能切开一个段，再加上新的段。预留好空间之后，
+            // 移除用户段（按需分配、只有 4KB 的页）和添加按需分配的段都不会再失败
+            self.segments
+                .try_reserve_additional(3)
+                .map_err(MmapError::NoMemory)?;
+            self.unmap_range(range).map_err(MmapError::NoMemory)?;
+            hint
+        } else {
+            let hint = VirtualPageNumber::floor(hint).start_address();
+            match user_range(hint, size) {
+                Ok(range) if !self.overlap_with(page_range(&range)) => hint,
+                _ => self.find_free_range(size).map_err(MmapError::NoMemory)?,
+            }
+        };
+        self.add_segment(
+            Segment {
+                map_type: MapType::Lazy,
+                range: start..start + size,
+                flags: flags | Flags::USER,
+            },
+            None,
+        )
+        .map_err(MmapError::NoMemory)?;
+        self.merge_segments();
+        Ok(start)
+    }
+
+    // 移除 `start` 开始、长度为 `length` 的区间内的所有用户映射
+    // 区间内没有映射的部分会被忽略
+    pub fn munmap(&mut self, start: VirtualAddress, length: usize) -> MemoryResult<()> {
+        let range = user_range(start, page_aligned_length(length)?)?;
+        self.unmap_range(range)
+    }
+
+    // 修改区间内所有页的权限，区间必须完全被用户映射覆盖
+    // 没有任何读写执行权限时，已经分配的物理页仍然保留，只是暂时从页表中移除
+    pub fn mprotect(
+        &mut self,
+        start: VirtualAddress,
+        length: usize,
+        flags: Flags,
+    ) -> MemoryResult<()> {
+        let range = user_range(start, page_aligned_length(length)?)?;
+        if !self.is_covered(&range) {
+            return Err("range is not fully mapped");
+        }
+        let flags = flags | Flags::USER;
+        self.split_at(range.start)?;
+        self.split_at(range.end)?;
+        for index in 0..self.segments.len() {
+            if !contains(&range, &self.segments[index].range) {
+                continue;
+            }
+            self.segments[index].flags = flags;
+            for vpn in self.segments[index].page_range() {
+                self.protect_page(vpn, flags)?;
+            }
+        }
+        self.merge_segments();
+        Ok(())
+    }
+
+    // 用户态能否对 `range` 进行 `access` 访问
+    // 区间必须位于用户地址空间中，其中的每一页都要落在带 `USER` 标志、权限允许的段中
+    pub fn user_can_access(&self, range: Range<VirtualAddress>, access: AccessType) -> bool {
+        if range.start >= range.end || range.end > USER_SPACE_END {
+            return false;
+        }
+        page_range(&range).all(|vpn| {
+            self.segments.iter().any(|segment| {
+                segment.page_range().contains(&vpn)
+                    && access.permitted_by(segment.flags, Privilege::User)
+            })
+        })
+    }
+
+    // 修改一页的权限，并保持页表与之一致
+    fn protect_page(&mut self, vpn: VirtualPageNumber, flags: Flags) -> MemoryResult<()> {
+        let accessible = flags.intersects(Flags::READABLE | Flags::WRITABLE | Flags::EXECUTABLE);
+        // 仍在写时复制共享的页，页表中不能有写权限
+        let flags = self.cow_flags(vpn, flags);
+        match (self.mapping.lookup(vpn), accessible) {
+            (Some(_), true) => self.mapping.update_flags(vpn, flags),
+            // 没有 R/W/X 的页表项会被硬件当作指向下一级页表的指针，因此只能移除映射
+            (Some(entry), false) => {
+                self.discard_stale_copy(vpn, entry.flags());
+                self.mapping.unmap_one(vpn)
+            }
+            // 之前被移除映射、但物理页还保留着的页，重新映射回来
+            (None, true) => match self.allocated_pairs.iter().find(|(v, _)| *v == vpn) {
+                Some((_, frame)) => {
+                    let ppn = frame.page_number();
+                    self.mapping.map_one(vpn, ppn, flags)
+                }
+                None => Ok(()),
+            },
+            (None, false) => Ok(()),
+        }
+    }
+
+    // 移除区间内的所有映射，区间内不能有内核的段
+    fn unmap_range(&mut self, range: Range<VirtualAddress>) -> MemoryResult<()> {
+        if self.overlaps_kernel(&range) {
+            return Err("range overlaps with a kernel segment");
+        }
+        self.split_at(range.start)?;
+        self.split_at(range.end)?;
+        while let Some(segment) = self
+            .segments
+            .iter()
+            .find(|segment| contains(&range, &segment.range))
+            .cloned()
+        {
+            self.remove_segment(&segment)?;
+        }
+        Ok(())
+    }
+
+    // 区间是否与内核的段（没有 `USER` 标志）重叠
+    fn overlaps_kernel(&self, range: &Range<VirtualAddress>) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| overlaps(range, &segment.range) && !segment.flags.contains(Flags::USER))
+    }
+
+    // 如果某个段跨越了地址 `va`，就把它从这里切成两段
+    // 页表和已经分配的物理页都不受影响，切开的两段仍然描述同样的映射
+    fn split_at(&mut self, va: VirtualAddress) -> MemoryResult<()> {
+        if let Some(index) = self
+            .segments
+            .iter()
+            .position(|segment| segment.range.start < va && va < segment.range.end)
+        {
+            self.segments.try_reserve_additional(1)?;
+            let mut right = self.segments[index].clone();
+            right.range.start = va;
+            self.segments[index].range.end = va;
+            self.segments.push(right);
+        }
+        Ok(())
+    }
+
+    // 把首尾相接、类型和权限都相同的用户段合并成一个
+    fn merge_segments(&mut self) {
+        self.segments.sort_by_key(|segment| segment.range.start);
+        let mut index = 0;
+        while index + 1 < self.segments.len() {
+            let (left, right) = (&self.segments[index], &self.segments[index + 1]);
+            if left.range.end == right.range.start
+                && left.map_type == MapType::Lazy
+                && right.map_type == MapType::Lazy
+                && left.flags == right.flags
+                && left.flags.contains(Flags::USER)
+            {
+                self.segments[index].range.end = self.segments[index + 1].range.end;
+                self.segments.remove(index + 1);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    // 区间是否完全被用户段覆盖
+    fn is_covered(&self, range: &Range<VirtualAddress>) -> bool {
+        let mut covered = range.start;
+        while covered < range.end {
+            match self.segments.iter().find(|segment| {
+                segment.range.start <= covered
+                    && covered < segment.range.end
+                    && segment.flags.contains(Flags::USER)
+            }) {
+                Some(segment) => covered = segment.range.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    // 从 USER_MMAP_BASE 开始，寻找第一段长度为 `size` 的空闲区域
+    fn find_free_range(&self, size: usize) -> MemoryResult<VirtualAddress> {
+        let mut candidate = USER_MMAP_BASE;
+        loop {
+            let end = candidate
+                .0
+                .checked_add(size)
+                .filter(|&end| end <= USER_SPACE_END.0)
+                .ok_or("no free range in user address space")?;
+            let range = candidate..VirtualAddress(end);
+            // 与候选区间重叠的段中，结束地址最高的那个之后才可能有空间
+            match self
+                .segments
+                .iter()
+                .filter(|segment| overlaps(&range, &segment.range))
+                .map(|segment| segment.range.end)
+                .max()
+            {
+                Some(end) => candidate = VirtualPageNumber::ceil(end).start_address(),
+                None => return Ok(candidate),
+            }
+        }
+    }
+}
+
+// 长度向上取整到页，长度为 0 或溢出时出错
+fn page_aligned_length(length: usize) -> MemoryResult<usize> {
+    match length.checked_next_multiple_of(PAGE_SIZE) {
+        Some(size) if size != 0 => Ok(size),
+        _ => Err("invalid mapping length"),
+    }
+}
+
+// 检查 `start` 开始、长度为 `size` 的区间是否位于用户地址空间中
+// 第 0 页不允许映射，保证空指针访问总会出错
+fn user_range(start: VirtualAddress, size: usize) -> MemoryResult<Range<VirtualAddress>> {
+    if !start.is_aligned() {
+        return Err("address is not page aligned");
+    }
+    match start.0.checked_add(size) {
+        Some(end) if start.0 >= PAGE_SIZE && end <= USER_SPACE_END.0 => {
+            Ok(start..VirtualAddress(end))
+        }
+        _ => Err("range is outside of user address space"),
+    }
+}
+
+fn page_range(range: &Range<VirtualAddress>) -> Range<VirtualPageNumber> {
+    VirtualPageNumber::floor(range.start)..VirtualPageNumber::ceil(range.end)
+}
+
+// 两个区间是否重叠
+fn overlaps(a: &Range<VirtualAddress>, b: &Range<VirtualAddress>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+// 区间 `outer` 是否包含区间 `inner`
+fn contains(outer: &Range<VirtualAddress>, inner: &Range<VirtualAddress>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
@@ -1,9 +1,26 @@
 // 声明该文件夹下的子模块，并设为公开（pub）供外部使用
 pub mod config;
 pub mod heap;
+pub mod layout;
 pub mod address;
+pub mod dma;
+pub mod frame;
+pub mod mapping;
+pub mod swap;
+
+// 一个缩写，模块中一些函数会使用
+// 出错时返回一段静态字符串作为错误描述
+pub type MemoryResult<T> = Result<T, &'static str>;
 
 // 内存模块的统一初始化入口
 pub fn init() {
     heap::init();
+    // 物理页分配器依赖堆（内部用 Vec 记录空闲区间），因此放在堆之后初始化
+    frame::init();
+    // 帧分配器就绪后，堆才能从中借来物理页扩展到初始大小
+    heap::configure(heap::HeapConfig::default());
+    // 帧分配器就绪后才能为页表分配物理页，按段重新映射内核并开启分页
+    mapping::init();
+    // 内存中的压缩交换区使用堆保存数据
+    swap::init();
 }
@@ -0,0 +1,118 @@
+// 块设备上的交换分区
+// 交换分区是块设备上一段连续的块，每个槽位占用其中连续的 PAGE_SIZE / block_size 个块，
+// 槽位的使用情况用位图记录。
+
+use super::SwapDevice;
+use crate::memory::{MemoryResult, config::PAGE_SIZE, heap::try_vec_with_capacity};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+// 块设备，由磁盘驱动实现
+pub trait BlockDevice: Send {
+    // 块的大小（字节），必须整除 PAGE_SIZE
+    fn block_size(&self) -> usize;
+    // 块的个数
+    fn block_count(&self) -> usize;
+    // 读出一个块，`buffer` 的长度为块的大小
+    fn read_block(&mut self, block: usize, buffer: &mut [u8]) -> MemoryResult<()>;
+    // 写入一个块，`buffer` 的长度为块的大小
+    fn write_block(&mut self, block: usize, buffer: &[u8]) -> MemoryResult<()>;
+}
+
+// 位于块设备 `blocks` 范围内的交换分区
+pub struct BlockSwap<D: BlockDevice> {
+    device: D,
+    // 交换分区的第一个块
+    start: usize,
+    // 每个槽位占用的块数
+    blocks_per_slot: usize,
+    // 槽位的个数
+    slot_count: usize,
+    // 第 i 位为 1 表示第 i 个槽位正在使用
+    bitmap: Vec<u64>,
+    used: usize,
+}
+
+#[allow(dead_code)]
+impl<D: BlockDevice> BlockSwap<D> {
+    // 使用块设备上 `blocks` 范围内的块作为交换分区
+    pub fn new(device: D, blocks: Range<usize>) -> MemoryResult<Self> {
+        let block_size = device.block_size();
+        if block_size == 0 || !PAGE_SIZE.is_multiple_of(block_size) {
+            return Err("block size does not divide the page size");
+        }
+        if blocks.end > device.block_count() {
+            return Err("swap partition is outside of the device");
+        }
+        let blocks_per_slot = PAGE_SIZE / block_size;
+        let slot_count = blocks.len() / blocks_per_slot;
+        let mut bitmap = try_vec_with_capacity(slot_count.div_ceil(64))?;
+        bitmap.resize(slot_count.div_ceil(64), 0);
+        Ok(Self {
+            device,
+            start: blocks.start,
+            blocks_per_slot,
+            slot_count,
+            bitmap,
+            used: 0,
+        })
+    }
+
+    // 槽位的个数
+    pub fn capacity(&self) -> usize {
+        self.slot_count
+    }
+
+    // 槽位中的每个块，以及它对应页中的字节范围
+    fn blocks(&self, slot: usize) -> impl Iterator<Item = (usize, Range<usize>)> + use<D> {
+        let (first, block_size) = (
+            self.start + slot * self.blocks_per_slot,
+            PAGE_SIZE / self.blocks_per_slot,
+        );
+        (0..self.blocks_per_slot).map(move |i| (first + i, i * block_size..(i + 1) * block_size))
+    }
+}
+
+impl<D: BlockDevice> SwapDevice for BlockSwap<D> {
+    fn alloc(&mut self) -> MemoryResult<usize> {
+        let (index, word) = self
+            .bitmap
+            .iter_mut()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)
+            .ok_or("swap partition is full")?;
+        let slot = index * 64 + word.trailing_ones() as usize;
+        if slot >= self.slot_count {
+            return Err("swap partition is full");
+        }
+        *word |= 1 << (slot % 64);
+        self.used += 1;
+        Ok(slot)
+    }
+
+    fn free(&mut self, slot: usize) {
+        let word = &mut self.bitmap[slot / 64];
+        if *word & (1 << (slot % 64)) != 0 {
+            *word &= !(1 << (slot % 64));
+            self.used -= 1;
+        }
+    }
+
+    fn write(&mut self, slot: usize, page: &[u8; PAGE_SIZE]) -> MemoryResult<()> {
+        for (block, range) in self.blocks(slot) {
+            self.device.write_block(block, &page[range])?;
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, slot: usize, page: &mut [u8; PAGE_SIZE]) -> MemoryResult<()> {
+        for (block, range) in self.blocks(slot) {
+            self.device.read_block(block, &mut page[range])?;
+        }
+        Ok(())
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+}
@@ -0,0 +1,188 @@
+// 内存中的压缩交换区（类似 Linux 的 zram）
+// 没有磁盘时，被换出的页压缩之后保存在内核堆中。用户页中常有大段的零或重复的字节，
+// 这里使用简单的游程编码（PackBits）：
+// - 控制字节 n < 128：后面跟着 n + 1 个原样保存的字节
+// - 控制字节 n >= 128：后面的一个字节重复 n - 125 次（3 ~ 130 次）
+// 压缩后不比原来小的页直接原样保存。堆空间不足时写入失败，而不是让整个内核因为分配失败而停下。
+
+use super::SwapDevice;
+use crate::memory::{MemoryResult, config::PAGE_SIZE};
+use alloc::vec::Vec;
+
+// 一段原样保存的字节最多有多长
+const MAX_LITERAL: usize = 128;
+// 重复多少次以上才按重复编码
+const MIN_RUN: usize = 3;
+// 一段重复编码最多表示多少个字节
+const MAX_RUN: usize = 130;
+
+// 压缩存储
+pub struct CompressedStore {
+    // 每个槽位中保存的数据，`None` 表示空闲；长度为 PAGE_SIZE 的数据是没有压缩的原始页
+    slots: Vec<Option<Vec<u8>>>,
+    // 空闲的槽位
+    free: Vec<usize>,
+    // 压缩后占用的总字节数
+    stored_bytes: usize,
+}
+
+impl CompressedStore {
+    pub const fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+            stored_bytes: 0,
+        }
+    }
+
+    // 压缩后占用的总字节数
+    #[allow(dead_code)]
+    pub fn stored_bytes(&self) -> usize {
+        self.stored_bytes
+    }
+}
+
+impl SwapDevice for CompressedStore {
+    fn alloc(&mut self) -> MemoryResult<usize> {
+        if let Some(slot) = self.free.pop() {
+            self.slots[slot] = Some(Vec::new());
+            return Ok(slot);
+        }
+        // 释放槽位时（可能在 drop 中）不能再分配内存，提前为空闲列表留好空间
+        let free_space = self.slots.len() + 1 - self.free.len();
+        self.slots
+            .try_reserve(1)
+            .and(self.free.try_reserve(free_space))
+            .map_err(|_| "out of memory for compressed swap")?;
+        self.slots.push(Some(Vec::new()));
+        Ok(self.slots.len() - 1)
+    }
+
+    fn free(&mut self, slot: usize) {
+        if let Some(data) = self.slots[slot].take() {
+            self.stored_bytes -= data.len();
+            self.free.push(slot);
+        }
+    }
+
+    fn write(&mut self, slot: usize, page: &[u8; PAGE_SIZE]) -> MemoryResult<()> {
+        let mut buffer = [0; PAGE_SIZE];
+        let compressed = match compress(page, &mut buffer) {
+            Some(length) => &buffer[..length],
+            None => &page[..],
+        };
+        let mut data = Vec::new();
+        data.try_reserve_exact(compressed.len())
+            .map_err(|_| "out of memory for compressed swap")?;
+        data.extend_from_slice(compressed);
+        let old = self.slots[slot]
+            .replace(data)
+            .ok_or("swap slot is not allocated")?;
+        self.stored_bytes = self.stored_bytes + compressed.len() - old.len();
+        Ok(())
+    }
+
+    fn read(&mut self, slot: usize, page: &mut [u8; PAGE_SIZE]) -> MemoryResult<()> {
+        let data = self.slots[slot]
+            .as_ref()
+            .ok_or("swap slot is not allocated")?;
+        if data.len() == PAGE_SIZE {
+            page.copy_from_slice(data);
+            Ok(())
+        } else {
+            decompress(data, page)
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    // 压缩后的数据直接复制一份即可
+    fn duplicate(&mut self, slot: usize) -> MemoryResult<usize> {
+        let mut data = Vec::new();
+        let source = self.slots[slot]
+            .as_ref()
+            .ok_or("swap slot is not allocated")?;
+        data.try_reserve_exact(source.len())
+            .map_err(|_| "out of memory for compressed swap")?;
+        data.extend_from_slice(source);
+        let copy = self.alloc()?;
+        self.stored_bytes += data.len();
+        self.slots[copy] = Some(data);
+        Ok(copy)
+    }
+}
+
+// 向 `output` 追加字节，空间不够时返回 `None`
+fn emit(output: &mut [u8], length: &mut usize, bytes: &[u8]) -> Option<()> {
+    output
+        .get_mut(*length..*length + bytes.len())?
+        .copy_from_slice(bytes);
+    *length += bytes.len();
+    Some(())
+}
+
+// 把 `literal` 中的字节按原样保存的格式写出，每段不超过 MAX_LITERAL 个字节
+fn emit_literal(output: &mut [u8], length: &mut usize, literal: &[u8]) -> Option<()> {
+    for chunk in literal.chunks(MAX_LITERAL) {
+        emit(output, length, &[(chunk.len() - 1) as u8])?;
+        emit(output, length, chunk)?;
+    }
+    Some(())
+}
+
+// 压缩一页，返回压缩后的长度；不比原来小时返回 `None`
+fn compress(page: &[u8; PAGE_SIZE], output: &mut [u8; PAGE_SIZE]) -> Option<usize> {
+    let mut length = 0;
+    // 还没有写出的原样保存的字节从这里开始
+    let mut literal = 0;
+    let mut i = 0;
+    while i < PAGE_SIZE {
+        let run = page[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&byte| byte == page[i])
+            .count();
+        if run >= MIN_RUN {
+            emit_literal(output, &mut length, &page[literal..i])?;
+            emit(output, &mut length, &[(run + 125) as u8, page[i]])?;
+            i += run;
+            literal = i;
+        } else {
+            i += 1;
+        }
+    }
+    emit_literal(output, &mut length, &page[literal..])?;
+    (length < PAGE_SIZE).then_some(length)
+}
+
+// 解压一页
+fn decompress(data: &[u8], page: &mut [u8; PAGE_SIZE]) -> MemoryResult<()> {
+    const CORRUPTED: &str = "corrupted compressed page";
+    let (mut i, mut length) = (0, 0);
+    while i < data.len() {
+        let control = data[i] as usize;
+        if control < 128 {
+            let count = control + 1;
+            let bytes = data.get(i + 1..i + 1 + count).ok_or(CORRUPTED)?;
+            page.get_mut(length..length + count)
+                .ok_or(CORRUPTED)?
+                .copy_from_slice(bytes);
+            i += 1 + count;
+            length += count;
+        } else {
+            let count = control - 125;
+            let byte = *data.get(i + 1).ok_or(CORRUPTED)?;
+            page.get_mut(length..length + count)
+                .ok_or(CORRUPTED)?
+                .fill(byte);
+            i += 2;
+            length += count;
+        }
+    }
+    if length != PAGE_SIZE {
+        return Err(CORRUPTED);
+    }
+    Ok(())
+}
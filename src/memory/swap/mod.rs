@@ -0,0 +1,136 @@
+// 交换区（swap）
+// 物理内存不够时，把不常用的用户页写到交换区中，腾出的帧留给别的用途；
+// 之后再访问这些页时触发缺页异常，从交换区读回来（见 `mapping/reclaim.rs`）。
+//
+// 交换区的存储由 [`SwapDevice`] 提供：有磁盘时使用 [`BlockSwap`]，按页写到块设备上的交换分区；
+// 没有磁盘时默认使用 [`CompressedStore`]，把页压缩后保存在内核堆中。
+
+mod block;
+mod compressed;
+
+#[allow(unused_imports)]
+pub use block::{BlockDevice, BlockSwap};
+pub use compressed::CompressedStore;
+
+use super::{MemoryResult, config::PAGE_SIZE};
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+// 交换区的存储设备，以页为单位读写，每一页占用一个槽位
+pub trait SwapDevice: Send {
+    // 分配一个空闲的槽位
+    fn alloc(&mut self) -> MemoryResult<usize>;
+    // 释放槽位
+    fn free(&mut self, slot: usize);
+    // 把一页写入槽位
+    fn write(&mut self, slot: usize, page: &[u8; PAGE_SIZE]) -> MemoryResult<()>;
+    // 从槽位中读出一页
+    fn read(&mut self, slot: usize, page: &mut [u8; PAGE_SIZE]) -> MemoryResult<()>;
+    // 正在使用的槽位数
+    fn used(&self) -> usize;
+
+    // 把一个槽位的内容复制到新的槽位中
+    fn duplicate(&mut self, slot: usize) -> MemoryResult<usize> {
+        let mut page = [0; PAGE_SIZE];
+        self.read(slot, &mut page)?;
+        let copy = self.alloc()?;
+        if let Err(error) = self.write(copy, &page) {
+            self.free(copy);
+            return Err(error);
+        }
+        Ok(copy)
+    }
+}
+
+// 当前使用的交换区
+static SWAP_DEVICE: Mutex<Option<Box<dyn SwapDevice>>> = Mutex::new(None);
+// 换出和换入的页数
+static PAGES_OUT: AtomicUsize = AtomicUsize::new(0);
+static PAGES_IN: AtomicUsize = AtomicUsize::new(0);
+
+// 初始化交换区，还没有设置块设备时使用内存中的压缩存储
+pub fn init() {
+    SWAP_DEVICE
+        .lock()
+        .get_or_insert_with(|| Box::new(CompressedStore::new()));
+    println!("mod swap initialized");
+}
+
+// 更换交换区的存储设备，例如找到磁盘之后改用上面的交换分区
+// 旧的交换区中还有页时不能更换
+#[allow(dead_code)]
+pub fn set_device(device: Box<dyn SwapDevice>) -> MemoryResult<()> {
+    let mut current = SWAP_DEVICE.lock();
+    if current.as_ref().is_some_and(|current| current.used() != 0) {
+        return Err("swap device is in use");
+    }
+    *current = Some(device);
+    Ok(())
+}
+
+// 在当前的交换区上执行操作
+fn with_device<T>(f: impl FnOnce(&mut dyn SwapDevice) -> MemoryResult<T>) -> MemoryResult<T> {
+    f(SWAP_DEVICE.lock().as_deref_mut().ok_or("no swap device")?)
+}
+
+// 交换区中的一个槽位，drop 时释放
+#[derive(Debug)]
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    // 分配一个槽位
+    pub fn alloc() -> MemoryResult<Self> {
+        with_device(|device| device.alloc()).map(Self)
+    }
+
+    // 把一页写入槽位
+    pub fn write(&self, page: &[u8; PAGE_SIZE]) -> MemoryResult<()> {
+        with_device(|device| device.write(self.0, page))?;
+        PAGES_OUT.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // 从槽位中读出一页
+    pub fn read(&self, page: &mut [u8; PAGE_SIZE]) -> MemoryResult<()> {
+        with_device(|device| device.read(self.0, page))?;
+        PAGES_IN.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // 复制出一个内容相同的槽位
+    pub fn duplicate(&self) -> MemoryResult<Self> {
+        with_device(|device| device.duplicate(self.0)).map(Self)
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        if let Some(device) = SWAP_DEVICE.lock().as_deref_mut() {
+            device.free(self.0);
+        }
+    }
+}
+
+// 交换区的统计
+#[derive(Debug, Copy, Clone)]
+pub struct SwapStats {
+    // 写入交换区的页数
+    pub pages_out: usize,
+    // 从交换区读回的页数
+    pub pages_in: usize,
+    // 正在使用的槽位数
+    pub used_slots: usize,
+}
+
+// 获取交换区的统计
+pub fn stats() -> SwapStats {
+    SwapStats {
+        pages_out: PAGES_OUT.load(Ordering::Relaxed),
+        pages_in: PAGES_IN.load(Ordering::Relaxed),
+        used_slots: SWAP_DEVICE
+            .lock()
+            .as_ref()
+            .map_or(0, |device| device.used()),
+    }
+}
@@ -6,9 +6,9 @@
 use core::arch::asm;
 // SBI 调用核心函数：这是内核请求 OpenSBI 服务的唯一标准入口
 // - which: 服务编号（Extension ID），放在 x17 寄存器
-// - arg0, arg1, arg2: 传递给服务的参数，分别放在 x10, x11, x12 寄存器
+// - arg0, arg1, arg2, arg3: 传递给服务的参数，分别放在 x10, x11, x12, x13 寄存器
 #[inline(always)]
-fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
     let mut ret; // 用于接收从寄存器返回的结果
     unsafe {
         asm!(
@@ -17,6 +17,7 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
             inlateout("x10") arg0 => ret,   // 输入：arg0 放入 x10；输出：执行后的 x10 存入 ret
             in("x11") arg1,                 // 输入：arg1 放入 x11
             in("x12") arg2,                 // 输入：arg2 放入 x12
+            in("x13") arg3,                 // 输入：arg3 放入 x13
             in("x17") which,                // 输入：服务编号放入 x17
         );
     }
@@ -38,24 +39,41 @@ const SBI_SHUTDOWN: usize = 8;               // 关闭操作系统（关机）
 // 向控制台输出一个字符
 // 注意：参数 c 使用 usize 而非 char，是因为底层寄存器处理的是字长大小的数据
 pub fn console_putchar(c: usize) {
-    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
+    sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0, 0);
 }
 
 // 从控制台中读取一个字符
 // 如果当前缓冲区没有字符，通常返回 -1
 pub fn console_getchar() -> usize {
-    sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
+    sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0, 0)
 }
 
 // 调用 SBI_SHUTDOWN 来关闭操作系统
 // -> ! 表示这个函数是“发散”的，即它永远不会返回（因为机器已经关了）
 pub fn shutdown() -> ! {
-    sbi_call(SBI_SHUTDOWN, 0, 0, 0);
+    sbi_call(SBI_SHUTDOWN, 0, 0, 0, 0);
     // 如果关机指令执行完程序还没停，说明出大问题了，手动标记为不可达
     unreachable!()
 }
 
 // 设置下一次时钟中断的时间
 pub fn set_timer(time: usize) {
-    sbi_call(SBI_SET_TIMER, time, 0, 0);
+    sbi_call(SBI_SET_TIMER, time, 0, 0, 0);
+}
+
+// 让 `hart_mask` 中的 hart 刷新 [start, start + size) 范围内所有地址空间的 TLB 缓存
+// `hart_mask` 是一个位图的地址，第 i 位对应 hart i；`size` 为 usize::MAX 时刷新整个 TLB
+pub fn remote_sfence_vma(hart_mask: &usize, start: usize, size: usize) {
+    sbi_call(SBI_REMOTE_SFENCE_VMA, hart_mask as *const usize as usize, start, size, 0);
+}
+
+// 同上，但只刷新地址空间标识符为 `asid` 的条目
+pub fn remote_sfence_vma_asid(hart_mask: &usize, start: usize, size: usize, asid: usize) {
+    sbi_call(
+        SBI_REMOTE_SFENCE_VMA_ASID,
+        hart_mask as *const usize as usize,
+        start,
+        size,
+        asid,
+    );
 }
@@ -0,0 +1,113 @@
+// 内存相关的系统调用：mmap、munmap、mprotect
+// 这里只负责检查参数、转换标志位，具体的映射由当前地址空间（[`MemorySet`]）完成。
+// 目前只支持私有的匿名映射。
+
+use super::*;
+use crate::memory::address::{VirtualAddress, VirtualPageNumber};
+use crate::memory::config::PAGE_SIZE;
+use crate::memory::mapping::{self, Flags, MmapError};
+
+// `prot` 参数
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+
+// `flags` 参数
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+const MAP_FIXED_NOREPLACE: usize = 0x10_0000;
+
+// 把 `prot` 转换为页表项的权限
+// RISC-V 不允许只写不读的页，因此可写的页同时也是可读的
+fn prot_to_flags(prot: usize) -> Result<Flags, isize> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(EINVAL);
+    }
+    let mut flags = Flags::empty();
+    if prot & PROT_READ != 0 {
+        flags |= Flags::READABLE;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= Flags::READABLE | Flags::WRITABLE;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags |= Flags::EXECUTABLE;
+    }
+    Ok(flags)
+}
+
+// 映射匿名内存，返回映射的起始地址
+// `addr` 在没有 MAP_FIXED 时只是建议的位置
+pub fn sys_mmap(
+    addr: usize,
+    length: usize,
+    prot: usize,
+    flags: usize,
+    _fd: usize,
+    offset: usize,
+) -> SyscallResult {
+    let permission = prot_to_flags(prot)?;
+    if length == 0 || !offset.is_multiple_of(PAGE_SIZE) {
+        return Err(EINVAL);
+    }
+    // 必须是私有映射或共享映射中的一种，目前还不支持共享映射
+    if flags & (MAP_SHARED | MAP_PRIVATE) != MAP_PRIVATE {
+        return Err(EINVAL);
+    }
+    // 还没有文件系统，不支持文件映射
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(ENODEV);
+    }
+    let fixed = flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0;
+    if fixed && !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(EINVAL);
+    }
+    let memory_set = mapping::current();
+    let mut memory_set = memory_set.lock();
+    // MAP_FIXED_NOREPLACE 不能替换已有的映射
+    if flags & MAP_FIXED_NOREPLACE != 0 {
+        let start = VirtualPageNumber::floor(VirtualAddress(addr));
+        let end = VirtualPageNumber::ceil(VirtualAddress(addr.saturating_add(length)));
+        if memory_set.overlap_with(start..end) {
+            return Err(EEXIST);
+        }
+    }
+    memory_set
+        .mmap(VirtualAddress(addr), length, permission, fixed)
+        .map(usize::from)
+        .map_err(|error| match error {
+            MmapError::Invalid(_) => EINVAL,
+            MmapError::NoMemory(_) => ENOMEM,
+        })
+}
+
+// 解除映射，区间内没有映射的部分会被忽略
+pub fn sys_munmap(addr: usize, length: usize) -> SyscallResult {
+    if length == 0 || !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(EINVAL);
+    }
+    mapping::current()
+        .lock()
+        .munmap(VirtualAddress(addr), length)
+        .map(|_| 0)
+        .map_err(|_| EINVAL)
+}
+
+// 修改权限，区间必须完全被映射
+pub fn sys_mprotect(addr: usize, length: usize, prot: usize) -> SyscallResult {
+    let permission = prot_to_flags(prot)?;
+    if !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(EINVAL);
+    }
+    // 长度为 0 时什么都不做
+    if length == 0 {
+        return Ok(0);
+    }
+    mapping::current()
+        .lock()
+        .mprotect(VirtualAddress(addr), length, permission)
+        .map(|_| 0)
+        .map_err(|_| ENOMEM)
+}
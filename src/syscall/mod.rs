@@ -0,0 +1,35 @@
+// 系统调用
+// 用户程序执行 ecall 陷入内核后，由中断处理程序交给 [`syscall`]：
+// a7 为系统调用号，a0 ~ a5 为参数，返回值写回 a0。
+// 系统调用号和错误码与 Linux（RISC-V）保持一致，出错时返回负的错误码。
+
+mod memory;
+
+// 系统调用号
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+
+// 错误码
+pub const ENOMEM: isize = 12;
+pub const EEXIST: isize = 17;
+pub const ENODEV: isize = 19;
+pub const EINVAL: isize = 22;
+pub const ENOSYS: isize = 38;
+
+// 系统调用的返回值，出错时为错误码（正数）
+pub type SyscallResult = Result<usize, isize>;
+
+// 执行一个系统调用，返回写回 a0 的值
+pub fn syscall(id: usize, args: [usize; 6]) -> isize {
+    let result = match id {
+        SYS_MMAP => memory::sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYS_MUNMAP => memory::sys_munmap(args[0], args[1]),
+        SYS_MPROTECT => memory::sys_mprotect(args[0], args[1], args[2]),
+        _ => Err(ENOSYS),
+    };
+    match result {
+        Ok(value) => value as isize,
+        Err(errno) => -errno,
+    }
+}